@@ -0,0 +1,153 @@
+use super::hitable;
+use super::material;
+use super::tiles;
+use super::vec3;
+
+/// Auxiliary buffers taken from the first hit of each camera ray. Pixels are
+/// stored in output order, top row first.
+pub struct Aovs {
+    /// Average surface albedo; rays that miss contribute the sky colour.
+    pub albedo: Vec<vec3::Vec3>,
+    /// Average shading normal; rays that miss contribute zero.
+    pub normal: Vec<vec3::Vec3>,
+    /// Average ray parameter `t` of the hits, which for the pinhole camera is
+    /// the depth along the view axis. Infinite where nothing was hit.
+    pub depth: Vec<f32>,
+    /// Average world-space position of the hits.
+    pub position: Vec<vec3::Vec3>,
+    /// Object, primitive and material of the first sample's hit, 0 for the
+    /// sky. Material IDs are numbered from 1 in order of first appearance in
+    /// the frame.
+    pub object_id: Vec<u32>,
+    pub primitive_id: Vec<u32>,
+    pub material_id: Vec<u32>,
+}
+
+impl Aovs {
+    /// Cuts `region` out of buffers for a frame `width` pixels wide.
+    pub fn crop(self, region: tiles::Region, width: i32) -> Aovs {
+        Aovs {
+            albedo: region.crop(&self.albedo, width),
+            normal: region.crop(&self.normal, width),
+            depth: region.crop(&self.depth, width),
+            position: region.crop(&self.position, width),
+            object_id: region.crop(&self.object_id, width),
+            primitive_id: region.crop(&self.primitive_id, width),
+            material_id: region.crop(&self.material_id, width),
+        }
+    }
+
+    /// Clears everything outside `region` of a frame `width` pixels wide to
+    /// what a pixel that hit nothing would hold.
+    pub fn mask(&mut self, region: tiles::Region, width: i32) {
+        let zero = vec3::Vec3::new(0, 0, 0);
+        region.mask(&mut self.albedo, width, zero);
+        region.mask(&mut self.normal, width, zero);
+        region.mask(&mut self.depth, width, f32::INFINITY);
+        region.mask(&mut self.position, width, zero);
+        region.mask(&mut self.object_id, width, 0);
+        region.mask(&mut self.primitive_id, width, 0);
+        region.mask(&mut self.material_id, width, 0);
+    }
+}
+
+/// Accumulates `Aovs` while rendering.
+pub struct AovBuffers {
+    albedo: Vec<vec3::Vec3>,
+    normal: Vec<vec3::Vec3>,
+    depth: Vec<f32>,
+    position: Vec<vec3::Vec3>,
+    object_id: Vec<u32>,
+    primitive_id: Vec<u32>,
+    /// Address of the first sample's material, numbered in `finish`.
+    material: Vec<Option<usize>>,
+    samples: Vec<u32>,
+    hits: Vec<u32>,
+}
+
+impl AovBuffers {
+    pub fn new(width: i32, height: i32) -> Self {
+        let n = (width * height) as usize;
+        Self {
+            albedo: vec![vec3::Vec3::new(0, 0, 0); n],
+            normal: vec![vec3::Vec3::new(0, 0, 0); n],
+            depth: vec![0.0; n],
+            position: vec![vec3::Vec3::new(0, 0, 0); n],
+            object_id: vec![0; n],
+            primitive_id: vec![0; n],
+            material: vec![None; n],
+            samples: vec![0; n],
+            hits: vec![0; n],
+        }
+    }
+
+    pub fn add_sample(
+        &mut self,
+        index: usize,
+        rec: Option<hitable::HitRecord>,
+        background: vec3::Vec3,
+    ) {
+        let first = self.samples[index] == 0;
+        self.samples[index] += 1;
+        match rec {
+            Some(rec) => {
+                self.hits[index] += 1;
+                self.albedo[index] += rec.material.albedo();
+                self.normal[index] += rec.normal;
+                self.depth[index] += rec.t;
+                self.position[index] += rec.p;
+                if first {
+                    self.object_id[index] = rec.object_id + 1;
+                    self.primitive_id[index] = rec.primitive_id + 1;
+                    let key = rec.material as *const dyn material::Material as *const () as usize;
+                    self.material[index] = Some(key);
+                }
+            }
+            None => self.albedo[index] += background,
+        }
+    }
+
+    pub fn finish(self) -> Aovs {
+        let mut material_ids: Vec<usize> = Vec::new();
+        let material_id = self
+            .material
+            .iter()
+            .map(|key| match key {
+                Some(key) => match material_ids.iter().position(|k| k == key) {
+                    Some(i) => i as u32 + 1,
+                    None => {
+                        material_ids.push(*key);
+                        material_ids.len() as u32
+                    }
+                },
+                None => 0,
+            })
+            .collect();
+
+        let average = |sum: vec3::Vec3, n: u32| {
+            if n == 0 {
+                sum
+            } else {
+                sum / n as f32
+            }
+        };
+        let n = self.samples.len();
+        Aovs {
+            albedo: (0..n).map(|i| average(self.albedo[i], self.samples[i])).collect(),
+            normal: (0..n).map(|i| average(self.normal[i], self.samples[i])).collect(),
+            depth: (0..n)
+                .map(|i| {
+                    if self.hits[i] == 0 {
+                        f32::INFINITY
+                    } else {
+                        self.depth[i] / self.hits[i] as f32
+                    }
+                })
+                .collect(),
+            position: (0..n).map(|i| average(self.position[i], self.hits[i])).collect(),
+            object_id: self.object_id,
+            primitive_id: self.primitive_id,
+            material_id,
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use super::pnm;
+use super::sampler;
+use super::vec3;
+use std::f32::consts::PI;
+use std::io;
+use std::path::Path;
+
+/// Greyscale aperture mask covering the square [-1, 1]^2 of the lens. Brighter
+/// pixels let through proportionally more light.
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    /// Running sum of pixel weights, used to pick a pixel by inversion.
+    cdf: Vec<f32>,
+}
+
+impl ApertureMask {
+    /// Loads a binary (P5) or plain (P2) PGM image.
+    pub fn load_pgm<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let image = pnm::read(path)?;
+        if image.channels != 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a PGM image".to_string(),
+            ));
+        }
+        let (width, height) = (image.width, image.height);
+
+        let mut cdf = Vec::with_capacity(width * height);
+        let mut total = 0.0;
+        for v in image.values {
+            total += v;
+            cdf.push(total);
+        }
+        if total <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "aperture mask is completely black".to_string(),
+            ));
+        }
+
+        Ok(Self { width, height, cdf })
+    }
+
+    fn sample(&self, sampler: &mut dyn sampler::Sampler) -> vec3::Vec3 {
+        let total = self.cdf[self.cdf.len() - 1];
+        let target = sampler.next_1d() * total;
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= target)
+            .min(self.cdf.len() - 1);
+        let (jx, jy) = sampler.next_2d();
+        let x = (index % self.width) as f32 + jx;
+        let y = (index / self.width) as f32 + jy;
+        vec3::Vec3::new(
+            2.0 * x / self.width as f32 - 1.0,
+            1.0 - 2.0 * y / self.height as f32,
+            0,
+        )
+    }
+}
+
+/// Shape of the lens opening, in units of the lens radius.
+pub enum Aperture {
+    /// The unit disk.
+    Circle,
+    /// Regular polygon with `blades` sides inscribed in the unit disk, rotated
+    /// by `rotation` degrees. Fewer than 3 blades are taken as 3.
+    Polygon { blades: u32, rotation: f32 },
+    /// The mask image stretched over the square [-1, 1]^2, keeping whatever
+    /// shape and coverage the image has, so it may reach past the unit disk.
+    Mask(ApertureMask),
+}
+
+impl Aperture {
+    /// Returns a point uniformly distributed over the aperture, with z = 0.
+    pub fn sample(&self, sampler: &mut dyn sampler::Sampler) -> vec3::Vec3 {
+        match self {
+            Aperture::Circle => random_in_unit_disk(sampler),
+            Aperture::Polygon { blades, rotation } => {
+                // All wedges have the same area, so pick one uniformly and then
+                // a uniform point in the triangle it spans with the centre.
+                let blades = (*blades).max(3);
+                let wedge = ((sampler.next_1d() * blades as f32) as u32).min(blades - 1);
+                let step = 2.0 * PI / blades as f32;
+                let a0 = rotation * PI / 180.0 + wedge as f32 * step;
+                let v0 = vec3::Vec3::new(a0.cos(), a0.sin(), 0);
+                let v1 = vec3::Vec3::new((a0 + step).cos(), (a0 + step).sin(), 0);
+                let (mut s, mut t) = sampler.next_2d();
+                if s + t > 1.0 {
+                    s = 1.0 - s;
+                    t = 1.0 - t;
+                }
+                s * v0 + t * v1
+            }
+            Aperture::Mask(mask) => mask.sample(sampler),
+        }
+    }
+}
+
+/// Shirley-Chiu concentric mapping, which keeps the stratification of the
+/// incoming 2D sample.
+fn random_in_unit_disk(sampler: &mut dyn sampler::Sampler) -> vec3::Vec3 {
+    let (x, y) = sampler.next_2d();
+    let a = 2.0 * x - 1.0;
+    let b = 2.0 * y - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return vec3::Vec3::new(0, 0, 0);
+    }
+    let (r, phi) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    vec3::Vec3::new(r * phi.cos(), r * phi.sin(), 0)
+}
@@ -0,0 +1,121 @@
+use super::hitable;
+use super::ray;
+use super::texture;
+use super::vec3;
+
+/// Smallest cosine allowed between a perturbed shading normal and the
+/// geometric normal.
+const MIN_COSINE: f32 = 0.01;
+
+/// Detail that changes a surface's shading normal without changing its shape.
+pub enum ShadingMap {
+    /// Tangent-space normal map. Red, green and blue in [0, 1] encode the
+    /// normal's components along `dpdu`, along `dpdv` and out of the surface,
+    /// as in the OpenGL (+Y) convention.
+    Normal(texture::ImageTexture),
+    /// Height map, whose values are scaled by `scale` into scene units. The
+    /// first channel is used.
+    Height {
+        texture: texture::ImageTexture,
+        scale: f32,
+    },
+}
+
+impl ShadingMap {
+    /// Perturbed outward-facing shading normal at `rec`. `outward` is the
+    /// outward normal it replaces.
+    fn perturb(&self, rec: &hitable::HitRecord, outward: vec3::Vec3) -> vec3::Vec3 {
+        // Tangent frame following the parameterisation, with `outward` as up.
+        let tangent = vec3::unit_vector(rec.dpdu - rec.dpdu.dot(outward) * outward);
+        let bitangent = outward.cross(tangent);
+        match self {
+            ShadingMap::Normal(texture) => {
+                let c = texture.value(rec);
+                let local = 2.0 * c - vec3::Vec3::new(1, 1, 1);
+                vec3::unit_vector(local.x() * tangent + local.y() * bitangent + local.z() * outward)
+            }
+            ShadingMap::Height { texture, scale } => {
+                // Finite differences one texel apart. Moving the surface by
+                // h along the normal changes its tangents by the height's
+                // derivatives times the normal.
+                let (du, dv) = (
+                    1.0 / (texture.mipmap.width() as f32 * texture.repeat.0),
+                    1.0 / (texture.mipmap.height() as f32 * texture.repeat.1),
+                );
+                let height = |u: f32, v: f32| {
+                    let mut shifted = *rec;
+                    shifted.u = u;
+                    shifted.v = v;
+                    scale * texture.value(&shifted).r()
+                };
+                let h = height(rec.u, rec.v);
+                let dhdu = (height(rec.u + du, rec.v) - h) / du;
+                let dhdv = (height(rec.u, rec.v + dv) - h) / dv;
+                let dpdu = rec.dpdu + dhdu * outward;
+                let dpdv = rec.dpdv + dhdv * outward;
+                let n = vec3::unit_vector(dpdu.cross(dpdv));
+                if n.dot(outward) < 0.0 {
+                    -n
+                } else {
+                    n
+                }
+            }
+        }
+    }
+}
+
+/// Wraps an object to perturb the shading normal of its hits with a normal
+/// or height map before any material sees them.
+pub struct Bumped {
+    pub object: Box<dyn hitable::Hitable>,
+    pub map: ShadingMap,
+}
+
+impl Bumped {
+    fn shade<'a>(&self, mut rec: hitable::HitRecord<'a>) -> hitable::HitRecord<'a> {
+        if rec.dpdu.squared_length() == 0.0 {
+            return rec;
+        }
+        let side = if rec.front_face { 1.0 } else { -1.0 };
+        let normal = side * self.map.perturb(&rec, side * rec.normal);
+        // A shading normal facing away from the ray makes no sense to the
+        // materials, so keep it above the geometric surface.
+        let ng = rec.geometric_normal;
+        let cosine = normal.dot(ng);
+        rec.normal = if cosine.is_nan() {
+            rec.normal
+        } else if cosine < MIN_COSINE {
+            // Tilt it towards the geometric normal, keeping its tangential
+            // direction, until the cosine is exactly `MIN_COSINE`.
+            let tangential = normal - cosine * ng;
+            let length = tangential.length();
+            if length > 0.0 {
+                MIN_COSINE * ng + (1.0 - MIN_COSINE * MIN_COSINE).sqrt() / length * tangential
+            } else {
+                ng
+            }
+        } else {
+            normal
+        };
+        rec
+    }
+}
+
+impl hitable::Hitable for Bumped {
+    fn hit(&self, r: ray::Ray, t_min: f32, t_max: f32) -> Option<hitable::HitRecord<'_>> {
+        self.object.hit(r, t_min, t_max).map(|rec| self.shade(rec))
+    }
+
+    fn occluded(&self, r: ray::Ray, t_min: f32, t_max: f32) -> bool {
+        self.object.occluded(r, t_min, t_max)
+    }
+
+    fn spans(&self, r: ray::Ray) -> Option<Vec<hitable::Span<'_>>> {
+        let mut spans = self.object.spans(r)?;
+        for span in spans.iter_mut() {
+            span.enter.rec = span.enter.rec.map(|rec| self.shade(rec));
+            span.exit.rec = span.exit.rec.map(|rec| self.shade(rec));
+        }
+        Some(spans)
+    }
+}
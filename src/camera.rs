@@ -1,9 +1,49 @@
+use super::aperture;
+use super::differential;
+use super::hitable;
 use super::ray;
+use super::sampler;
 use super::vec3;
 use std::f32::consts::PI;
 
 pub trait Camera {
-    fn get_ray(&self, u: f32, v: f32) -> ray::Ray;
+    fn get_ray(&self, u: f32, v: f32, sampler: &mut dyn sampler::Sampler) -> ray::Ray;
+
+    /// Whether `(u, v)` lies inside the image the camera forms. Samples
+    /// outside it are black.
+    fn sees(&self, _u: f32, _v: f32) -> bool {
+        true
+    }
+
+    /// Like `get_ray`, but also returns the rays through `(u + du, v)` and
+    /// `(u, v + dv)`. They reuse the lens sample of the main ray, so only the
+    /// change in image position separates them from it.
+    fn get_ray_differential(
+        &self,
+        u: f32,
+        v: f32,
+        du: f32,
+        dv: f32,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (ray::Ray, differential::Differential) {
+        ray_differential(self, u, v, du, dv, sampler)
+    }
+}
+
+fn ray_differential<C: Camera + ?Sized>(
+    camera: &C,
+    u: f32,
+    v: f32,
+    du: f32,
+    dv: f32,
+    sampler: &mut dyn sampler::Sampler,
+) -> (ray::Ray, differential::Differential) {
+    let mut recorder = sampler::Recorder::<MAX_RAY_DRAWS>::new(sampler);
+    let r = camera.get_ray(u, v, &mut recorder);
+    let values = recorder.values();
+    let rx = camera.get_ray(u + du, v, &mut sampler::Replay::new(values));
+    let ry = camera.get_ray(u, v + dv, &mut sampler::Replay::new(values));
+    (r, differential::Differential::new(rx, ry))
 }
 
 #[derive(Copy, Clone)]
@@ -40,7 +80,7 @@ impl NoBlurCamera {
 }
 
 impl Camera for NoBlurCamera {
-    fn get_ray(&self, u: f32, v: f32) -> ray::Ray {
+    fn get_ray(&self, u: f32, v: f32, _sampler: &mut dyn sampler::Sampler) -> ray::Ray {
         ray::Ray {
             a: self.origin,
             b: self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin,
@@ -48,7 +88,39 @@ impl Camera for NoBlurCamera {
     }
 }
 
-#[derive(Copy, Clone)]
+/// Focus distance for a camera looking through frame coordinates `(s, t)`:
+/// the distance along the view axis to the first surface hit, or `None` if
+/// the ray escapes the scene.
+#[allow(clippy::too_many_arguments)]
+pub fn autofocus(
+    world: &dyn hitable::Hitable,
+    lookfrom: vec3::Vec3,
+    lookat: vec3::Vec3,
+    vup: vec3::Vec3,
+    vfov: f32,
+    aspect: f32,
+    s: f32,
+    t: f32,
+) -> Option<f32> {
+    // The pinhole camera's image plane sits at unit distance, so the ray
+    // parameter of a hit is already its depth along the view axis.
+    let r = NoBlurCamera::new(lookfrom, lookat, vup, vfov, aspect).get_ray(
+        s,
+        t,
+        &mut sampler::RandomSampler::new(0),
+    );
+    world.hit(r, 0.001, f32::INFINITY).map(|rec| rec.t)
+}
+
+/// Give up on cat's-eye rejection after this many lens samples and fall back to
+/// the lens centre; only reachable when the clipping disk barely overlaps the
+/// aperture.
+const VIGNETTING_MAX_TRIES: u32 = 64;
+
+/// Most values a camera draws for one ray: up to three per aperture sample,
+/// which vignetting may retry.
+const MAX_RAY_DRAWS: usize = 3 * VIGNETTING_MAX_TRIES as usize;
+
 pub struct DefocusBlurCamera {
     origin: vec3::Vec3,
     lower_left_corner: vec3::Vec3,
@@ -56,8 +128,9 @@ pub struct DefocusBlurCamera {
     vertical: vec3::Vec3,
     u: vec3::Vec3,
     v: vec3::Vec3,
-    w: vec3::Vec3,
     lens_radius: f32,
+    aperture: aperture::Aperture,
+    vignetting: f32,
 }
 
 impl DefocusBlurCamera {
@@ -84,29 +157,46 @@ impl DefocusBlurCamera {
                                - half_height * focus_dist * v - focus_dist * w,
             horizontal: 2.0 * half_width * focus_dist * u,
             vertical: 2.0 * half_height * focus_dist * v,
-            u: u,
-            v: v,
-            w: w,
+            u,
+            v,
             lens_radius: aperture / 2.0,
+            aperture: aperture::Aperture::Circle,
+            vignetting: 0.0,
         }
     }
 
-    fn random_in_unit_disk() -> vec3::Vec3 {
-        let mut p: vec3::Vec3;
-        loop {
-            p = 2.0 * vec3::Vec3::new(rand::random::<f32>(), rand::random::<f32>(), 0)
-                - vec3::Vec3::new(1, 1, 0);
-            if !(p.dot(p) >= 1.0) {
-                break;
+    /// Replaces the default circular aperture, e.g. with a bladed polygon.
+    pub fn with_aperture(mut self, aperture: aperture::Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    /// Optical vignetting strength. The lens barrel clips the aperture with a
+    /// unit disk that moves `vignetting` units off-centre at the frame edges,
+    /// which turns off-axis bokeh into cat's eyes. 0 disables it.
+    pub fn with_vignetting(mut self, vignetting: f32) -> Self {
+        self.vignetting = vignetting;
+        self
+    }
+
+    fn sample_lens(&self, s: f32, t: f32, sampler: &mut dyn sampler::Sampler) -> vec3::Vec3 {
+        if self.vignetting == 0.0 {
+            return self.aperture.sample(sampler);
+        }
+        let centre = self.vignetting * vec3::Vec3::new(2.0 * s - 1.0, 2.0 * t - 1.0, 0);
+        for _ in 0..VIGNETTING_MAX_TRIES {
+            let p = self.aperture.sample(sampler);
+            if (p - centre).squared_length() < 1.0 {
+                return p;
             }
         }
-        return p;
+        vec3::Vec3::new(0, 0, 0)
     }
 }
 
 impl Camera for DefocusBlurCamera {
-    fn get_ray(&self, u: f32, v: f32) -> ray::Ray {
-        let rd = self.lens_radius * DefocusBlurCamera::random_in_unit_disk();
+    fn get_ray(&self, u: f32, v: f32, sampler: &mut dyn sampler::Sampler) -> ray::Ray {
+        let rd = self.lens_radius * self.sample_lens(u, v, sampler);
         let offset = self.u * rd.x() + self.v * rd.y();
         ray::Ray {
             a: self.origin + offset,
@@ -114,3 +204,390 @@ impl Camera for DefocusBlurCamera {
         }
     }
 }
+
+/// Diagonal of a 35mm (36x24 mm) film frame, used to define equivalent focal
+/// lengths for any aspect ratio.
+const FULL_FRAME_DIAGONAL_MM: f32 = 43.266_615;
+
+/// Photographic description of a camera, as a front end to `DefocusBlurCamera`.
+/// Scene units are taken to be metres.
+#[derive(Copy, Clone)]
+pub struct PhysicalCamera {
+    /// 35mm-equivalent focal length in millimetres.
+    pub focal_length: f32,
+    pub f_number: f32,
+    /// Shutter speed in seconds. Only affects exposure; the scene is static.
+    pub shutter: f32,
+    pub iso: f32,
+}
+
+impl PhysicalCamera {
+    /// Vertical field of view in degrees for a frame of the given aspect ratio
+    /// with the same diagonal as 35mm film.
+    pub fn vfov(&self, aspect: f32) -> f32 {
+        let frame_height = FULL_FRAME_DIAGONAL_MM / (1.0 + aspect * aspect).sqrt();
+        2.0 * (frame_height / (2.0 * self.focal_length)).atan() * 180.0 / PI
+    }
+
+    /// Entrance pupil diameter in scene units.
+    pub fn aperture(&self) -> f32 {
+        self.focal_length / self.f_number / 1000.0
+    }
+
+    /// Scale applied to scene radiance. Normalised so that the sunny 16 rule
+    /// (f/16 at 1/ISO seconds) gives 1, which suits the daylight sky.
+    pub fn exposure(&self) -> f32 {
+        self.shutter * self.iso * 256.0 / (self.f_number * self.f_number)
+    }
+
+    pub fn camera(
+        &self,
+        lookfrom: vec3::Vec3,
+        lookat: vec3::Vec3,
+        vup: vec3::Vec3,
+        aspect: f32,
+        focus_dist: f32,
+    ) -> DefocusBlurCamera {
+        DefocusBlurCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            self.vfov(aspect),
+            aspect,
+            self.aperture(),
+            focus_dist,
+        )
+    }
+}
+
+impl Default for PhysicalCamera {
+    fn default() -> Self {
+        Self {
+            focal_length: 50.0,
+            f_number: 2.8,
+            shutter: 1.0 / 3200.0,
+            iso: 100.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct OrthographicCamera {
+    lower_left_corner: vec3::Vec3,
+    horizontal: vec3::Vec3,
+    vertical: vec3::Vec3,
+    direction: vec3::Vec3,
+}
+
+impl OrthographicCamera {
+    /// `view_height` is the height of the visible region in world units.
+    pub fn new(
+        lookfrom: vec3::Vec3,
+        lookat: vec3::Vec3,
+        vup: vec3::Vec3,
+        view_height: f32,
+        aspect: f32,
+    ) -> Self {
+        let half_height = view_height / 2.0;
+        let half_width = aspect * half_height;
+
+        let w = vec3::unit_vector(lookfrom - lookat);
+        let u = vec3::unit_vector(vup.cross(w));
+        let v = w.cross(u);
+
+        Self {
+            lower_left_corner: lookfrom - half_width * u - half_height * v,
+            horizontal: 2.0 * half_width * u,
+            vertical: 2.0 * half_height * v,
+            direction: -w,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, u: f32, v: f32, _sampler: &mut dyn sampler::Sampler) -> ray::Ray {
+        ray::Ray {
+            a: self.lower_left_corner + u * self.horizontal + v * self.vertical,
+            b: self.direction,
+        }
+    }
+}
+
+/// Equidistant fisheye: the angle from the view axis grows linearly with the
+/// distance from the image centre. The image circle touches the top and bottom
+/// edges of the frame and spans `fov`; the frame outside it is black.
+#[derive(Copy, Clone)]
+pub struct FisheyeCamera {
+    origin: vec3::Vec3,
+    u: vec3::Vec3,
+    v: vec3::Vec3,
+    w: vec3::Vec3,
+    half_fov: f32,
+    aspect: f32,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        lookfrom: vec3::Vec3,
+        lookat: vec3::Vec3,
+        vup: vec3::Vec3,
+        fov: f32,
+        aspect: f32,
+    ) -> Self {
+        let w = vec3::unit_vector(lookfrom - lookat);
+        let u = vec3::unit_vector(vup.cross(w));
+        let v = w.cross(u);
+
+        Self {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            half_fov: fov * PI / 360.0,
+            aspect,
+        }
+    }
+}
+
+impl FisheyeCamera {
+    /// Position on the image plane, in units of the image circle's radius.
+    fn image_point(&self, u: f32, v: f32) -> (f32, f32) {
+        ((2.0 * u - 1.0) * self.aspect, 2.0 * v - 1.0)
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, u: f32, v: f32, _sampler: &mut dyn sampler::Sampler) -> ray::Ray {
+        let (x, y) = self.image_point(u, v);
+        let theta = (x * x + y * y).sqrt() * self.half_fov;
+        let phi = y.atan2(x);
+        ray::Ray {
+            a: self.origin,
+            b: theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w,
+        }
+    }
+
+    fn sees(&self, u: f32, v: f32) -> bool {
+        let (x, y) = self.image_point(u, v);
+        x * x + y * y <= 1.0
+    }
+}
+
+/// Full 360x180 degree latitude-longitude panorama, centred on `lookat`.
+/// Frames should use a 2:1 aspect ratio so that pixels are square in angle.
+#[derive(Copy, Clone)]
+pub struct EquirectangularCamera {
+    origin: vec3::Vec3,
+    u: vec3::Vec3,
+    v: vec3::Vec3,
+    w: vec3::Vec3,
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: vec3::Vec3, lookat: vec3::Vec3, vup: vec3::Vec3) -> Self {
+        let w = vec3::unit_vector(lookfrom - lookat);
+        let u = vec3::unit_vector(vup.cross(w));
+        let v = w.cross(u);
+
+        Self {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, u: f32, v: f32, _sampler: &mut dyn sampler::Sampler) -> ray::Ray {
+        let longitude = (u - 0.5) * 2.0 * PI;
+        let latitude = (v - 0.5) * PI;
+        ray::Ray {
+            a: self.origin,
+            b: latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+                + latitude.sin() * self.v,
+        }
+    }
+}
+
+/// How the two eyes of a stereo pair are packed into one frame. The left eye
+/// goes in the left half (`SideBySide`) or the top half (`TopBottom`).
+#[derive(Copy, Clone, PartialEq)]
+pub enum StereoLayout {
+    SideBySide,
+    TopBottom,
+}
+
+impl StereoLayout {
+    /// Maps frame coordinates to `(is_left_eye, u, v)` within that eye's view.
+    fn split(self, u: f32, v: f32) -> (bool, f32, f32) {
+        match self {
+            StereoLayout::SideBySide => {
+                if u < 0.5 {
+                    (true, 2.0 * u, v)
+                } else {
+                    (false, 2.0 * u - 1.0, v)
+                }
+            }
+            StereoLayout::TopBottom => {
+                if v >= 0.5 {
+                    (true, u, 2.0 * v - 1.0)
+                } else {
+                    (false, u, 2.0 * v)
+                }
+            }
+        }
+    }
+
+    /// Turns the steps `du` and `dv` around where they would cross into the
+    /// other eye, so ray differentials stay within the eye of `(u, v)`.
+    fn steps(self, u: f32, v: f32, du: f32, dv: f32) -> (f32, f32) {
+        let eye = self.split(u, v).0;
+        let du = if self.split(u + du, v).0 == eye { du } else { -du };
+        let dv = if self.split(u, v + dv).0 == eye { dv } else { -dv };
+        (du, dv)
+    }
+
+    /// Aspect ratio of a single eye given the aspect ratio of the whole frame.
+    fn eye_aspect(self, aspect: f32) -> f32 {
+        match self {
+            StereoLayout::SideBySide => aspect / 2.0,
+            StereoLayout::TopBottom => aspect * 2.0,
+        }
+    }
+}
+
+/// Parallel-axis stereo pair. Both eyes share an image plane at `convergence`
+/// distance (an off-axis frustum), so objects at that depth have zero parallax.
+#[derive(Copy, Clone)]
+pub struct StereoCamera {
+    left: NoBlurCamera,
+    right: NoBlurCamera,
+    layout: StereoLayout,
+}
+
+impl StereoCamera {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        lookfrom: vec3::Vec3,
+        lookat: vec3::Vec3,
+        vup: vec3::Vec3,
+        vfov: f32,
+        aspect: f32,
+        interocular: f32,
+        convergence: f32,
+        layout: StereoLayout,
+    ) -> Self {
+        let theta = vfov * PI / 180.0;
+        let half_height = (theta / 2.0).tan();
+        let half_width = layout.eye_aspect(aspect) * half_height;
+
+        let w = vec3::unit_vector(lookfrom - lookat);
+        let u = vec3::unit_vector(vup.cross(w));
+        let v = w.cross(u);
+
+        let lower_left_corner = lookfrom - half_width * convergence * u
+            - half_height * convergence * v - convergence * w;
+        let horizontal = 2.0 * half_width * convergence * u;
+        let vertical = 2.0 * half_height * convergence * v;
+        let eye = |offset: f32| NoBlurCamera {
+            origin: lookfrom + offset * u,
+            lower_left_corner,
+            horizontal,
+            vertical,
+        };
+
+        Self {
+            left: eye(-interocular / 2.0),
+            right: eye(interocular / 2.0),
+            layout,
+        }
+    }
+}
+
+impl Camera for StereoCamera {
+    fn get_ray(&self, u: f32, v: f32, sampler: &mut dyn sampler::Sampler) -> ray::Ray {
+        let (is_left, u, v) = self.layout.split(u, v);
+        if is_left {
+            self.left.get_ray(u, v, sampler)
+        } else {
+            self.right.get_ray(u, v, sampler)
+        }
+    }
+
+    fn get_ray_differential(
+        &self,
+        u: f32,
+        v: f32,
+        du: f32,
+        dv: f32,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (ray::Ray, differential::Differential) {
+        let (du, dv) = self.layout.steps(u, v, du, dv);
+        ray_differential(self, u, v, du, dv, sampler)
+    }
+}
+
+/// Omni-directional stereo panorama: an equirectangular view per eye, with
+/// each ray starting on a circle of diameter `interocular` tangent to its
+/// direction. The offset fades out towards the poles to avoid eye swapping
+/// when looking straight up or down.
+#[derive(Copy, Clone)]
+pub struct OdsCamera {
+    origin: vec3::Vec3,
+    u: vec3::Vec3,
+    v: vec3::Vec3,
+    w: vec3::Vec3,
+    interocular: f32,
+    layout: StereoLayout,
+}
+
+impl OdsCamera {
+    pub fn new(
+        lookfrom: vec3::Vec3,
+        lookat: vec3::Vec3,
+        vup: vec3::Vec3,
+        interocular: f32,
+        layout: StereoLayout,
+    ) -> Self {
+        let w = vec3::unit_vector(lookfrom - lookat);
+        let u = vec3::unit_vector(vup.cross(w));
+        let v = w.cross(u);
+
+        Self {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            interocular,
+            layout,
+        }
+    }
+}
+
+impl Camera for OdsCamera {
+    fn get_ray(&self, u: f32, v: f32, _sampler: &mut dyn sampler::Sampler) -> ray::Ray {
+        let (is_left, u, v) = self.layout.split(u, v);
+        let longitude = (u - 0.5) * 2.0 * PI;
+        let latitude = (v - 0.5) * PI;
+        let right = longitude.cos() * self.u + longitude.sin() * self.w;
+        let side = if is_left { -0.5 } else { 0.5 };
+        ray::Ray {
+            a: self.origin + side * self.interocular * latitude.cos() * right,
+            b: latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+                + latitude.sin() * self.v,
+        }
+    }
+
+    fn get_ray_differential(
+        &self,
+        u: f32,
+        v: f32,
+        du: f32,
+        dv: f32,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (ray::Ray, differential::Differential) {
+        let (du, dv) = self.layout.steps(u, v, du, dv);
+        ray_differential(self, u, v, du, dv, sampler)
+    }
+}
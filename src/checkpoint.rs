@@ -0,0 +1,370 @@
+use super::film;
+use super::render::PixelStats;
+use super::settings;
+use super::vec3;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"RTCKPT02";
+
+/// Bytes of the header after the magic, not counting the seeds.
+const HEADER_BYTES: u64 = 4 + 4 + 8 + 1 + 1 + 4 + 4 + 4;
+
+/// Bytes per pixel on disk: the RGB sum and weight as `f32`, the luminance
+/// sums as `f64` and the sample count as `u32`.
+const PIXEL_BYTES: u64 = 4 * 4 + 2 * 8 + 4;
+
+/// Filters in the order of their codes on disk.
+const FILTERS: [film::Filter; 5] = [
+    film::Filter::Box,
+    film::Filter::Tent,
+    film::Filter::Gaussian,
+    film::Filter::MitchellNetravali,
+    film::Filter::Lanczos,
+];
+
+/// The settings that decide which samples a render takes and how they land on
+/// the film. Renders only continue or combine when these match: a different
+/// scene seed is a different scene, and the sample count sets the strata of
+/// the stratified sampler.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Setup {
+    pub scene_seed: u64,
+    pub sampler: settings::SamplerKind,
+    pub filter: film::Filter,
+    pub filter_radius: f32,
+    /// Samples per pixel the render is taken to.
+    pub samples: i32,
+}
+
+impl Setup {
+    pub fn new(settings: &settings::RenderSettings) -> Self {
+        Self {
+            scene_seed: settings.scene_seed.unwrap_or(settings.seed),
+            sampler: settings.sampler,
+            filter: settings.filter,
+            filter_radius: settings
+                .filter_radius
+                .unwrap_or(settings.filter.default_radius()),
+            samples: settings.ns,
+        }
+    }
+
+    /// Describes the first setting in which the checkpoint `other` differs
+    /// from `self`.
+    fn check(&self, other: &Setup) -> Result<(), String> {
+        let sampler_name = |kind: settings::SamplerKind| {
+            settings::SamplerKind::ALL
+                .iter()
+                .find(|(_, k)| *k == kind)
+                .map_or("?", |(name, _)| *name)
+        };
+        if self.scene_seed != other.scene_seed {
+            Err(format!(
+                "the checkpoint has scene seed {} instead of {}",
+                other.scene_seed, self.scene_seed
+            ))
+        } else if self.sampler != other.sampler {
+            Err(format!(
+                "the checkpoint uses the {} sampler instead of {}",
+                sampler_name(other.sampler),
+                sampler_name(self.sampler)
+            ))
+        } else if self.filter != other.filter || self.filter_radius != other.filter_radius {
+            Err("the checkpoint uses a different reconstruction filter".to_string())
+        } else if self.samples != other.samples {
+            Err(format!(
+                "the checkpoint is taken to {} samples per pixel instead of {}",
+                other.samples, self.samples
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The state of a render in progress: the film's weighted sums and the sample
+/// statistics of every pixel. Samplers are reseeded from the seed, the pixel
+/// and the sample index for every camera sample, so the seed and each pixel's
+/// sample count are all the random number state needed to carry on.
+pub struct Checkpoint {
+    pub width: i32,
+    pub height: i32,
+    pub setup: Setup,
+    /// Seeds of the renders that went into this checkpoint.
+    pub seeds: Vec<u64>,
+    pub sums: Vec<vec3::Vec3>,
+    pub weights: Vec<f32>,
+    pub stats: Vec<PixelStats>,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint next to `path` first and then renames it, so that
+    /// an interrupted write never replaces a good checkpoint.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        {
+            let mut out = BufWriter::new(File::create(&partial)?);
+            out.write_all(MAGIC)?;
+            out.write_all(&self.width.to_le_bytes())?;
+            out.write_all(&self.height.to_le_bytes())?;
+            let setup = &self.setup;
+            let sampler = settings::SamplerKind::ALL
+                .iter()
+                .position(|(_, kind)| *kind == setup.sampler)
+                .unwrap_or(0);
+            let filter = FILTERS.iter().position(|f| *f == setup.filter).unwrap_or(0);
+            out.write_all(&setup.scene_seed.to_le_bytes())?;
+            out.write_all(&[sampler as u8, filter as u8])?;
+            out.write_all(&setup.filter_radius.to_le_bytes())?;
+            out.write_all(&setup.samples.to_le_bytes())?;
+            out.write_all(&(self.seeds.len() as u32).to_le_bytes())?;
+            for seed in &self.seeds {
+                out.write_all(&seed.to_le_bytes())?;
+            }
+            for index in 0..self.stats.len() {
+                for c in self.sums[index].e.iter() {
+                    out.write_all(&c.to_le_bytes())?;
+                }
+                out.write_all(&self.weights[index].to_le_bytes())?;
+                let stats = &self.stats[index];
+                out.write_all(&stats.luminance_sum.to_le_bytes())?;
+                out.write_all(&stats.luminance_squared_sum.to_le_bytes())?;
+                out.write_all(&stats.count.to_le_bytes())?;
+            }
+            out.flush()?;
+        }
+        std::fs::rename(&partial, path)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a render checkpoint"));
+        }
+        let width = i32::from_le_bytes(read_bytes(&mut input)?);
+        let height = i32::from_le_bytes(read_bytes(&mut input)?);
+        if width <= 0 || height <= 0 {
+            return Err(invalid("invalid checkpoint size"));
+        }
+        let scene_seed = u64::from_le_bytes(read_bytes(&mut input)?);
+        let [sampler, filter] = read_bytes(&mut input)?;
+        let setup = Setup {
+            scene_seed,
+            sampler: settings::SamplerKind::ALL
+                .get(sampler as usize)
+                .map(|(_, kind)| *kind)
+                .ok_or_else(|| invalid("unknown checkpoint sampler"))?,
+            filter: *FILTERS
+                .get(filter as usize)
+                .ok_or_else(|| invalid("unknown checkpoint filter"))?,
+            filter_radius: f32::from_le_bytes(read_bytes(&mut input)?),
+            samples: i32::from_le_bytes(read_bytes(&mut input)?),
+        };
+        let seed_count = u32::from_le_bytes(read_bytes(&mut input)?);
+
+        // The size comes from the file, so check that the file really holds
+        // that many pixels before allocating anything for them.
+        let n = (width as usize)
+            .checked_mul(height as usize)
+            .filter(|&n| {
+                let header = MAGIC.len() as u64 + HEADER_BYTES + 8 * seed_count as u64;
+                (n as u64)
+                    .checked_mul(PIXEL_BYTES)
+                    .and_then(|pixels| pixels.checked_add(header))
+                    == Some(file_len)
+            })
+            .ok_or_else(|| invalid("checkpoint size does not match its length"))?;
+        let seeds = (0..seed_count)
+            .map(|_| Ok(u64::from_le_bytes(read_bytes(&mut input)?)))
+            .collect::<io::Result<Vec<u64>>>()?;
+
+        let mut sums = Vec::with_capacity(n);
+        let mut weights = Vec::with_capacity(n);
+        let mut stats = Vec::with_capacity(n);
+        for _ in 0..n {
+            let mut sum = vec3::Vec3::new(0, 0, 0);
+            for c in sum.e.iter_mut() {
+                *c = f32::from_le_bytes(read_bytes(&mut input)?);
+            }
+            sums.push(sum);
+            weights.push(f32::from_le_bytes(read_bytes(&mut input)?));
+            stats.push(PixelStats {
+                luminance_sum: f64::from_le_bytes(read_bytes(&mut input)?),
+                luminance_squared_sum: f64::from_le_bytes(read_bytes(&mut input)?),
+                count: u32::from_le_bytes(read_bytes(&mut input)?),
+            });
+        }
+        Ok(Self {
+            width,
+            height,
+            setup,
+            seeds,
+            sums,
+            weights,
+            stats,
+        })
+    }
+
+    /// Checks that a render with `settings` can carry on from the checkpoint.
+    /// With zero samples the render only combines checkpoints, so any sample
+    /// count they were taken to will do.
+    pub fn check_resumable(&self, settings: &settings::RenderSettings) -> Result<(), String> {
+        if (self.width, self.height) != (settings.nx, settings.ny) {
+            return Err(format!(
+                "checkpoint is {}x{} but the render is {}x{}",
+                self.width, self.height, settings.nx, settings.ny
+            ));
+        }
+        let mut setup = Setup::new(settings);
+        if setup.samples == 0 {
+            setup.samples = self.setup.samples;
+        }
+        setup.check(&self.setup)
+    }
+
+    /// Adds the samples of another partial render of the same frame and
+    /// setup. The two must have been rendered with different seeds, or they
+    /// would contain the same samples twice.
+    pub fn merge(&mut self, other: &Checkpoint) -> Result<(), String> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return Err(format!(
+                "cannot merge a {}x{} checkpoint into a {}x{} one",
+                other.width, other.height, self.width, self.height
+            ));
+        }
+        self.setup.check(&other.setup)?;
+        if let Some(seed) = other.seeds.iter().find(|seed| self.seeds.contains(seed)) {
+            return Err(format!("both checkpoints were rendered with seed {}", seed));
+        }
+        self.seeds.extend_from_slice(&other.seeds);
+        for index in 0..self.stats.len() {
+            self.sums[index] += other.sums[index];
+            self.weights[index] += other.weights[index];
+            let (stats, more) = (&mut self.stats[index], &other.stats[index]);
+            stats.luminance_sum += more.luminance_sum;
+            stats.luminance_squared_sum += more.luminance_squared_sum;
+            stats.count += more.count;
+        }
+        Ok(())
+    }
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.ckpt", name, std::process::id()))
+    }
+
+    fn setup() -> Setup {
+        Setup {
+            scene_seed: 3,
+            sampler: settings::SamplerKind::Stratified,
+            filter: film::Filter::Gaussian,
+            filter_radius: 1.5,
+            samples: 16,
+        }
+    }
+
+    fn checkpoint(seed: u64, setup: Setup) -> Checkpoint {
+        Checkpoint {
+            width: 3,
+            height: 2,
+            setup,
+            seeds: vec![seed],
+            sums: (0..6).map(|i| vec3::Vec3::new(i, 0.5, -1.25)).collect(),
+            weights: (0..6).map(|i| i as f32 * 0.75).collect(),
+            stats: (0..6)
+                .map(|i| PixelStats {
+                    luminance_sum: i as f64 * 0.1,
+                    luminance_squared_sum: i as f64 * 0.01,
+                    count: i,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn write_then_read_round_trips() {
+        let checkpoint = checkpoint(7, setup());
+        let path = temp_path("round-trip");
+        checkpoint.write(&path).unwrap();
+        let read = Checkpoint::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((read.width, read.height), (3, 2));
+        assert!(read.setup == checkpoint.setup);
+        assert_eq!(read.seeds, checkpoint.seeds);
+        for i in 0..6 {
+            assert_eq!(read.sums[i].e, checkpoint.sums[i].e);
+            assert_eq!(read.weights[i], checkpoint.weights[i]);
+            assert_eq!(read.stats[i].luminance_sum, checkpoint.stats[i].luminance_sum);
+            assert_eq!(
+                read.stats[i].luminance_squared_sum,
+                checkpoint.stats[i].luminance_squared_sum
+            );
+            assert_eq!(read.stats[i].count, checkpoint.stats[i].count);
+        }
+    }
+
+    #[test]
+    fn rejects_a_size_the_file_does_not_hold() {
+        let path = temp_path("oversized");
+        checkpoint(7, setup()).write(&path).unwrap();
+        let mut data = std::fs::read(&path).unwrap();
+        data[8..12].copy_from_slice(&i32::MAX.to_le_bytes());
+        data[12..16].copy_from_slice(&i32::MAX.to_le_bytes());
+        std::fs::write(&path, &data).unwrap();
+        let result = Checkpoint::read(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let error = result.err().expect("an oversized checkpoint was accepted");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn merges_only_matching_setups() {
+        let mut merged = checkpoint(1, setup());
+        assert!(merged.merge(&checkpoint(2, setup())).is_ok());
+        assert_eq!(merged.seeds, vec![1, 2]);
+
+        let others = [
+            Setup {
+                scene_seed: 4,
+                ..setup()
+            },
+            Setup {
+                sampler: settings::SamplerKind::Sobol,
+                ..setup()
+            },
+            Setup {
+                filter: film::Filter::Box,
+                ..setup()
+            },
+            Setup {
+                samples: 32,
+                ..setup()
+            },
+        ];
+        for other in others.iter() {
+            let mut merged = checkpoint(1, setup());
+            assert!(merged.merge(&checkpoint(2, *other)).is_err());
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use super::hitable;
+use super::ray;
+use super::stats;
+use super::vec3;
+
+/// How a `Csg` node combines the solids of its two operands.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Operation {
+    /// Inside either operand.
+    Union,
+    /// Inside both operands.
+    Intersection,
+    /// Inside `left` but not `right`.
+    Difference,
+}
+
+impl Operation {
+    fn contains(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            Operation::Union => in_left || in_right,
+            Operation::Intersection => in_left && in_right,
+            Operation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Constructive solid geometry: the solid that `operation` makes of two
+/// closed objects. Hits keep the record, and so the material, of the operand
+/// whose surface they lie on.
+pub struct Csg {
+    operation: Operation,
+    left: Box<dyn hitable::Hitable>,
+    right: Box<dyn hitable::Hitable>,
+}
+
+impl Csg {
+    /// Fails unless both operands are closed, since an object without spans
+    /// would make the whole node vanish.
+    pub fn new(
+        operation: Operation,
+        left: Box<dyn hitable::Hitable>,
+        right: Box<dyn hitable::Hitable>,
+    ) -> Result<Self, String> {
+        // Whether `spans` is `None` does not depend on the ray, so any will do.
+        let probe = ray::Ray {
+            a: vec3::Vec3::new(0, 0, 0),
+            b: vec3::Vec3::new(0, 0, 1),
+        };
+        if left.spans(probe).is_none() || right.spans(probe).is_none() {
+            return Err("CSG operands must be closed objects".to_string());
+        }
+        Ok(Self {
+            operation,
+            left,
+            right,
+        })
+    }
+}
+
+/// Merges the spans of two solids along the same ray into those of their
+/// combination, by walking the crossings of both in order and keeping the ones
+/// where the ray enters or leaves the result.
+///
+/// A crossing keeps its surface's normal, which already faces the ray, but
+/// `front_face` is set from the result: true where the ray enters it. On faces
+/// carved out by a difference this flips the right operand's outside to the
+/// inside, so that e.g. glass bends light the right way.
+pub fn combine<'a>(
+    operation: Operation,
+    left: Vec<hitable::Span<'a>>,
+    right: Vec<hitable::Span<'a>>,
+) -> Vec<hitable::Span<'a>> {
+    // Each event is a crossing, whether it belongs to the left operand, and
+    // whether the ray enters that operand there.
+    let mut events = Vec::with_capacity(2 * (left.len() + right.len()));
+    for (spans, is_left) in [(left, true), (right, false)].iter() {
+        for span in spans {
+            events.push((span.enter, *is_left, true));
+            events.push((span.exit, *is_left, false));
+        }
+    }
+    // Entries go before exits at the same `t`, so that solids which just
+    // touch merge in a union rather than leaving a gap between them.
+    events.sort_by(|a, b| a.0.t.total_cmp(&b.0.t).then(b.2.cmp(&a.2)));
+
+    let oriented = |mut crossing: hitable::Crossing<'a>, entering: bool| {
+        if let Some(rec) = crossing.rec.as_mut() {
+            rec.front_face = entering;
+        }
+        crossing
+    };
+    let (mut in_left, mut in_right) = (false, false);
+    let mut enter = None;
+    let mut spans = Vec::new();
+    for (crossing, is_left, entering) in events {
+        let was_inside = operation.contains(in_left, in_right);
+        if is_left {
+            in_left = entering;
+        } else {
+            in_right = entering;
+        }
+        match (was_inside, operation.contains(in_left, in_right)) {
+            (false, true) => enter = Some(oriented(crossing, true)),
+            (true, false) => match enter.take() {
+                // Solids that only touch share no volume.
+                Some(enter) if crossing.t > enter.t => spans.push(hitable::Span {
+                    enter,
+                    exit: oriented(crossing, false),
+                }),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    spans
+}
+
+impl hitable::Hitable for Csg {
+    fn hit(&self, r: ray::Ray, t_min: f32, t_max: f32) -> Option<hitable::HitRecord<'_>> {
+        stats::count_hit(stats::Primitive::Csg);
+        self.spans(r)?
+            .iter()
+            .flat_map(|span| [span.enter, span.exit])
+            .find(|crossing| crossing.t > t_min && crossing.t < t_max)
+            .and_then(|crossing| crossing.rec)
+    }
+
+    fn spans(&self, r: ray::Ray) -> Option<Vec<hitable::Span<'_>>> {
+        let (left, right) = (self.left.spans(r)?, self.right.spans(r)?);
+        Some(combine(self.operation, left, right))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitable::Hitable;
+    use crate::material;
+    use crate::sphere;
+
+    fn sphere(z: f32, radius: f32) -> Box<dyn hitable::Hitable> {
+        Box::new(sphere::Sphere {
+            center: vec3::Vec3::new(0, 0, z),
+            radius,
+            material: Box::new(material::Lambertian {
+                albedo: vec3::Vec3::new(0.5, 0.5, 0.5),
+            }),
+        })
+    }
+
+    /// `(t, front_face)` of each crossing of the spans of `left` and `right`
+    /// combined, along the z axis from z = -5.
+    fn crossings(
+        operation: Operation,
+        left_z: f32,
+        right_z: f32,
+        right_radius: f32,
+    ) -> Vec<(f32, bool)> {
+        let left = sphere(left_z, 1.0);
+        let csg = Csg::new(operation, left, sphere(right_z, right_radius)).unwrap();
+        let r = ray::Ray {
+            a: vec3::Vec3::new(0, 0, -5),
+            b: vec3::Vec3::new(0, 0, 1),
+        };
+        csg.spans(r)
+            .unwrap()
+            .iter()
+            .flat_map(|span| [span.enter, span.exit])
+            .map(|crossing| (crossing.t, crossing.rec.unwrap().front_face))
+            .collect()
+    }
+
+    #[test]
+    fn difference_hollows_a_sphere() {
+        assert_eq!(
+            crossings(Operation::Difference, 0.0, 0.0, 0.5),
+            vec![(4.0, true), (4.5, false), (5.5, true), (6.0, false)]
+        );
+    }
+
+    #[test]
+    fn intersection_makes_a_lens() {
+        assert_eq!(
+            crossings(Operation::Intersection, -0.5, 0.5, 1.0),
+            vec![(4.5, true), (5.5, false)]
+        );
+    }
+
+    #[test]
+    fn union_merges_touching_spheres() {
+        assert_eq!(
+            crossings(Operation::Union, 0.0, 2.0, 1.0),
+            vec![(4.0, true), (8.0, false)]
+        );
+    }
+
+    #[test]
+    fn intersection_of_touching_spheres_is_empty() {
+        assert_eq!(crossings(Operation::Intersection, 0.0, 2.0, 1.0), vec![]);
+    }
+
+    /// An object without an inside.
+    struct Open;
+
+    impl hitable::Hitable for Open {
+        fn hit(&self, _r: ray::Ray, _t_min: f32, _t_max: f32) -> Option<hitable::HitRecord<'_>> {
+            None
+        }
+    }
+
+    #[test]
+    fn rejects_open_operands() {
+        assert!(Csg::new(Operation::Union, sphere(0.0, 1.0), Box::new(Open)).is_err());
+        assert!(Csg::new(Operation::Union, Box::new(Open), sphere(0.0, 1.0)).is_err());
+    }
+}
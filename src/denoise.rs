@@ -0,0 +1,95 @@
+use super::aov;
+use super::vec3;
+
+/// Number of à-trous passes; the filter footprint doubles with each one.
+const ITERATIONS: u32 = 5;
+
+/// B3-spline taps used at every scale of the wavelet.
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Edge-stopping strengths for colour, normal and albedo differences.
+const SIGMA_COLOR: f32 = 0.6;
+const SIGMA_NORMAL: f32 = 0.3;
+const SIGMA_ALBEDO: f32 = 0.1;
+
+/// Below this albedo a channel is filtered as is rather than demodulated.
+const MIN_ALBEDO: f32 = 0.01;
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) guided by the
+/// albedo and normal AOVs. The HDR colour is divided by the albedo before
+/// filtering so that texture detail is put back untouched afterwards.
+pub fn denoise(
+    width: i32,
+    height: i32,
+    pixels: &[vec3::Vec3],
+    aovs: &aov::Aovs,
+) -> Vec<vec3::Vec3> {
+    let demodulate = |c: f32, a: f32| if a > MIN_ALBEDO { c / a } else { c };
+    let remodulate = |c: f32, a: f32| if a > MIN_ALBEDO { c * a } else { c };
+
+    let mut current: Vec<vec3::Vec3> = pixels
+        .iter()
+        .zip(aovs.albedo.iter())
+        .map(|(&c, &a)| {
+            vec3::Vec3::new(
+                demodulate(c.r(), a.r()),
+                demodulate(c.g(), a.g()),
+                demodulate(c.b(), a.b()),
+            )
+        })
+        .collect();
+
+    let (w, h) = (width as usize, height as usize);
+    let mut next = current.clone();
+    for iteration in 0..ITERATIONS {
+        let step = 1i32 << iteration;
+        // Later passes compare against an already smoothed image, so the
+        // colour term is tightened as the footprint grows.
+        let sigma_color = SIGMA_COLOR / (1 << iteration) as f32;
+        for y in 0..h {
+            for x in 0..w {
+                let p = y * w + x;
+                let (cp, np, ap) = (current[p], aovs.normal[p], aovs.albedo[p]);
+                let mut sum = vec3::Vec3::new(0, 0, 0);
+                let mut weight_sum = 0.0;
+                for (j, ky) in KERNEL.iter().enumerate() {
+                    let qy = y as i32 + (j as i32 - 2) * step;
+                    if qy < 0 || qy >= h as i32 {
+                        continue;
+                    }
+                    for (i, kx) in KERNEL.iter().enumerate() {
+                        let qx = x as i32 + (i as i32 - 2) * step;
+                        if qx < 0 || qx >= w as i32 {
+                            continue;
+                        }
+                        let q = qy as usize * w + qx as usize;
+                        let cq = current[q];
+                        let w_color =
+                            (-(cp - cq).squared_length() / (sigma_color * sigma_color)).exp();
+                        let dn = np - aovs.normal[q];
+                        let w_normal = (-dn.squared_length() / (SIGMA_NORMAL * SIGMA_NORMAL)).exp();
+                        let da = ap - aovs.albedo[q];
+                        let w_albedo = (-da.squared_length() / (SIGMA_ALBEDO * SIGMA_ALBEDO)).exp();
+                        let weight = kx * ky * w_color * w_normal * w_albedo;
+                        sum += weight * cq;
+                        weight_sum += weight;
+                    }
+                }
+                next[p] = sum / weight_sum;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+    }
+
+    current
+        .iter()
+        .zip(aovs.albedo.iter())
+        .map(|(&c, &a)| {
+            vec3::Vec3::new(
+                remodulate(c.r(), a.r()),
+                remodulate(c.g(), a.g()),
+                remodulate(c.b(), a.b()),
+            )
+        })
+        .collect()
+}
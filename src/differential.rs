@@ -0,0 +1,125 @@
+use super::hitable;
+use super::ray;
+use super::vec3;
+
+/// Rays through the points one pixel to the right (`x`) and one pixel up
+/// (`y`) of a camera ray. Following them along with the ray tells how large
+/// the pixel's footprint is wherever the ray lands, which is what texture
+/// filtering needs. Directions are unit length.
+#[derive(Copy, Clone)]
+pub struct Differential {
+    pub rx_origin: vec3::Vec3,
+    pub rx_direction: vec3::Vec3,
+    pub ry_origin: vec3::Vec3,
+    pub ry_direction: vec3::Vec3,
+}
+
+impl Differential {
+    pub fn new(rx: ray::Ray, ry: ray::Ray) -> Self {
+        Self {
+            rx_origin: rx.origin(),
+            rx_direction: vec3::unit_vector(rx.direction()),
+            ry_origin: ry.origin(),
+            ry_direction: vec3::unit_vector(ry.direction()),
+        }
+    }
+
+    /// Intersects the offset rays with the tangent plane at `rec`, storing the
+    /// resulting changes of `u` and `v` in `rec`. Returns the offsets from the
+    /// hit point to the two intersections, which `scatter` needs.
+    pub fn project(&self, rec: &mut hitable::HitRecord) -> (vec3::Vec3, vec3::Vec3) {
+        let n = rec.geometric_normal;
+        let d = n.dot(rec.p);
+        let p = rec.p;
+        let offset = |origin: vec3::Vec3, direction: vec3::Vec3| {
+            let t = (d - n.dot(origin)) / n.dot(direction);
+            if t.is_finite() {
+                origin + t * direction - p
+            } else {
+                vec3::Vec3::new(0, 0, 0)
+            }
+        };
+        let dpdx = offset(self.rx_origin, self.rx_direction);
+        let dpdy = offset(self.ry_origin, self.ry_direction);
+
+        // dp = du * dpdu + dv * dpdv is overdetermined, so solve it in the two
+        // coordinates that the normal is least aligned with.
+        let (a, b) = if n.x().abs() > n.y().abs() && n.x().abs() > n.z().abs() {
+            (1, 2)
+        } else if n.y().abs() > n.z().abs() {
+            (0, 2)
+        } else {
+            (0, 1)
+        };
+        let (dpdu, dpdv) = (rec.dpdu.e, rec.dpdv.e);
+        let det = dpdu[a] * dpdv[b] - dpdv[a] * dpdu[b];
+        let solve = |dp: vec3::Vec3| {
+            if det.abs() < 1e-12 {
+                return (0.0, 0.0);
+            }
+            (
+                (dpdv[b] * dp.e[a] - dpdv[a] * dp.e[b]) / det,
+                (dpdu[a] * dp.e[b] - dpdu[b] * dp.e[a]) / det,
+            )
+        };
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+        rec.dudx = dudx;
+        rec.dvdx = dvdx;
+        rec.dudy = dudy;
+        rec.dvdy = dvdy;
+        (dpdx, dpdy)
+    }
+
+    /// Differential of `scattered`, the ray a specular surface sends on from
+    /// `rec`, given the offsets returned by `project`. Mirror reflection and
+    /// refraction are told apart by the side of the surface `scattered`
+    /// leaves on, and the relative index of refraction follows from the two
+    /// angles by Snell's law. Returns `None` at normal incidence, where those
+    /// angles do not determine it.
+    pub fn scatter(
+        &self,
+        r_in: ray::Ray,
+        rec: &hitable::HitRecord,
+        offsets: (vec3::Vec3, vec3::Vec3),
+        scattered: ray::Ray,
+    ) -> Option<Differential> {
+        let n = rec.normal;
+        let wo = -vec3::unit_vector(r_in.direction());
+        let wi = vec3::unit_vector(scattered.direction());
+        let cos_o = wo.dot(n);
+        let cos_i = wi.dot(n);
+        let dndx = rec.dndu * rec.dudx + rec.dndv * rec.dvdx;
+        let dndy = rec.dndu * rec.dudy + rec.dndv * rec.dvdy;
+        let dwodx = -self.rx_direction - wo;
+        let dwody = -self.ry_direction - wo;
+        let ddndx = dwodx.dot(n) + wo.dot(dndx);
+        let ddndy = dwody.dot(n) + wo.dot(dndy);
+
+        let (rx_direction, ry_direction) = if cos_i > 0.0 {
+            (
+                wi - dwodx + 2.0 * (cos_o * dndx + ddndx * n),
+                wi - dwody + 2.0 * (cos_o * dndy + ddndy * n),
+            )
+        } else {
+            let sin_o = wo.cross(n).length();
+            if sin_o < 1e-4 {
+                return None;
+            }
+            let eta = wi.cross(n).length() / sin_o;
+            let cos_t = -cos_i;
+            let mu = eta * cos_o - cos_t;
+            let dmu = eta - eta * eta * cos_o / cos_t;
+            (
+                wi - eta * dwodx + mu * dndx + dmu * ddndx * n,
+                wi - eta * dwody + mu * dndy + dmu * ddndy * n,
+            )
+        };
+        Some(Differential {
+            rx_origin: rec.p + offsets.0,
+            rx_direction: vec3::unit_vector(rx_direction),
+            ry_origin: rec.p + offsets.1,
+            ry_direction: vec3::unit_vector(ry_direction),
+        })
+    }
+}
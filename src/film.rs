@@ -0,0 +1,154 @@
+use super::vec3;
+use std::f32::consts::PI;
+
+/// Pixel reconstruction filters. All are separable and evaluated on offsets
+/// measured in pixels from the pixel centre.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    MitchellNetravali,
+    Lanczos,
+}
+
+impl Filter {
+    pub fn default_radius(self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::MitchellNetravali => 2.0,
+            Filter::Lanczos => 3.0,
+        }
+    }
+
+    /// One-dimensional filter profile; `x` is within `[-radius, radius)`.
+    fn evaluate_1d(self, x: f32, radius: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => (radius - x).max(0.0),
+            Filter::Gaussian => {
+                let alpha = 2.0;
+                ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.0)
+            }
+            Filter::MitchellNetravali => {
+                // B = C = 1/3, stretched so the support matches the radius.
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = 2.0 * x / radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else if x < 2.0 {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Lanczos => sinc(x) * sinc(x / radius),
+        }
+    }
+
+    fn evaluate(self, dx: f32, dy: f32, radius: f32) -> f32 {
+        self.evaluate_1d(dx, radius) * self.evaluate_1d(dy, radius)
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    let x = PI * x;
+    x.sin() / x
+}
+
+/// Accumulates filtered samples. Every sample is splatted into all pixels
+/// whose centre lies within the filter radius, weighted by the filter.
+pub struct Film {
+    width: i32,
+    height: i32,
+    filter: Filter,
+    radius: f32,
+    sums: Vec<vec3::Vec3>,
+    weights: Vec<f32>,
+}
+
+impl Film {
+    pub fn new(width: i32, height: i32, filter: Filter, radius: f32) -> Self {
+        let n = (width * height) as usize;
+        Self {
+            width,
+            height,
+            filter,
+            radius,
+            sums: vec![vec3::Vec3::new(0, 0, 0); n],
+            weights: vec![0.0; n],
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Adds a sample at continuous raster position `(x, y)`, with `y` counted
+    /// upwards from the bottom edge as in the camera's `v` coordinate.
+    pub fn add_sample(&mut self, x: f32, y: f32, col: vec3::Vec3) {
+        let r = self.radius;
+        let x0 = (x - 0.5 - r).ceil().max(0.0) as i32;
+        let x1 = (x - 0.5 + r).floor().min((self.width - 1) as f32) as i32;
+        let y0 = (y - 0.5 - r).ceil().max(0.0) as i32;
+        let y1 = (y - 0.5 + r).floor().min((self.height - 1) as f32) as i32;
+        for j in y0..=y1 {
+            let dy = y - (j as f32 + 0.5);
+            if dy < -r || dy >= r {
+                continue;
+            }
+            for i in x0..=x1 {
+                let dx = x - (i as f32 + 0.5);
+                if dx < -r || dx >= r {
+                    continue;
+                }
+                let weight = self.filter.evaluate(dx, dy, r);
+                let index = ((self.height - 1 - j) * self.width + i) as usize;
+                self.sums[index] += weight * col;
+                self.weights[index] += weight;
+            }
+        }
+    }
+
+    /// Weighted sample sums and filter weights of every pixel, top row first.
+    pub fn accumulated(&self) -> (&[vec3::Vec3], &[f32]) {
+        (&self.sums, &self.weights)
+    }
+
+    /// Adds sums and weights accumulated elsewhere, e.g. by an earlier run.
+    pub fn add_accumulated(&mut self, sums: &[vec3::Vec3], weights: &[f32]) {
+        for (index, (&sum, &weight)) in sums.iter().zip(weights.iter()).enumerate() {
+            self.sums[index] += sum;
+            self.weights[index] += weight;
+        }
+    }
+
+    /// Filtered radiance of every pixel, top row first. Negative filter lobes
+    /// can ring below zero, so the result is clamped.
+    pub fn pixels(&self) -> Vec<vec3::Vec3> {
+        self.sums
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(&sum, &weight)| {
+                if weight.abs() < 1e-6 {
+                    return vec3::Vec3::new(0, 0, 0);
+                }
+                let col = sum / weight;
+                vec3::Vec3::new(col.r().max(0.0), col.g().max(0.0), col.b().max(0.0))
+            })
+            .collect()
+    }
+}
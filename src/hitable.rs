@@ -1,17 +1,89 @@
+use super::csg;
 use super::material;
 use super::ray;
+use super::stats;
 use super::vec3;
 
 #[derive(Copy, Clone)]
 pub struct HitRecord<'a> {
     pub t: f32,
     pub p: vec3::Vec3,
+    /// Unit normal used for shading. Like `geometric_normal` it is on the side
+    /// the ray came from, but it may be perturbed, e.g. by a bump map.
     pub normal: vec3::Vec3,
-    pub material: &'a Box<dyn material::Material>,
+    /// Unit normal of the actual surface, on the side the ray came from.
+    pub geometric_normal: vec3::Vec3,
+    /// Whether the ray hit the outside of the surface. A sphere with a
+    /// negative radius is inside out, so its outside faces its centre.
+    pub front_face: bool,
+    /// Surface parameterisation at the hit, each in [0, 1].
+    pub u: f32,
+    pub v: f32,
+    /// Derivatives of `p` with respect to `u` and `v`, tangent to the surface.
+    pub dpdu: vec3::Vec3,
+    pub dpdv: vec3::Vec3,
+    /// Derivatives of `normal` with respect to `u` and `v`.
+    pub dndu: vec3::Vec3,
+    pub dndv: vec3::Vec3,
+    /// Changes of `u` and `v` towards the neighbouring pixels, filled in from
+    /// the ray's differential. They are zero when the ray has none, which
+    /// makes textures use their finest detail.
+    pub dudx: f32,
+    pub dvdx: f32,
+    pub dudy: f32,
+    pub dvdy: f32,
+    pub material: &'a dyn material::Material,
+    /// Index of the hit object in the outermost `HitableList`.
+    pub object_id: u32,
+    /// Index of the hit primitive within its object, such as a triangle of a
+    /// mesh. Always 0 for single-primitive shapes.
+    pub primitive_id: u32,
+}
+
+impl HitRecord<'_> {
+    /// Orients an outward-facing unit normal against the ray and returns it
+    /// with the front-face flag.
+    pub fn face_normal(r: ray::Ray, outward_normal: vec3::Vec3) -> (vec3::Vec3, bool) {
+        if r.direction().dot(outward_normal) < 0.0 {
+            (outward_normal, true)
+        } else {
+            (-outward_normal, false)
+        }
+    }
+}
+
+/// A point where a ray crosses the surface of a solid. The ends of stretches
+/// that run off to infinity have an infinite `t` and no hit record.
+#[derive(Copy, Clone)]
+pub struct Crossing<'a> {
+    pub t: f32,
+    pub rec: Option<HitRecord<'a>>,
+}
+
+/// A stretch of a ray inside a solid, from where it enters to where it leaves.
+#[derive(Copy, Clone)]
+pub struct Span<'a> {
+    pub enter: Crossing<'a>,
+    pub exit: Crossing<'a>,
 }
 
 pub trait Hitable {
-    fn hit(&self, r: ray::Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    fn hit(&self, r: ray::Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
+
+    /// Whether anything is hit between `t_min` and `t_max`. Unlike `hit` it
+    /// may stop at the first intersection found and never builds a
+    /// `HitRecord`, which is all shadow and occlusion rays need.
+    fn occluded(&self, r: ray::Ray, t_min: f32, t_max: f32) -> bool {
+        self.hit(r, t_min, t_max).is_some()
+    }
+
+    /// The stretches of the whole line through `r`, behind its origin too,
+    /// that lie inside the object, in order. Only closed objects have an
+    /// inside, so others return `None`, whatever the ray, and cannot take part
+    /// in CSG.
+    fn spans(&self, _r: ray::Ray) -> Option<Vec<Span<'_>>> {
+        None
+    }
 }
 
 pub struct HitableList {
@@ -19,17 +91,31 @@ pub struct HitableList {
 }
 
 impl Hitable for HitableList {
-    fn hit(&self, r: ray::Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, r: ray::Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        stats::count_hit(stats::Primitive::HitableList);
         let mut rec: Option<HitRecord> = None;
 
         let mut closest_so_far = t_max;
-        for obj in self.list.iter() {
-            let curr_rec = obj.hit(r, t_min, closest_so_far);
-            if !curr_rec.is_none() {
-                closest_so_far = curr_rec.unwrap().t;
-                rec = curr_rec;
+        for (index, obj) in self.list.iter().enumerate() {
+            if let Some(mut curr_rec) = obj.hit(r, t_min, closest_so_far) {
+                closest_so_far = curr_rec.t;
+                curr_rec.object_id = index as u32;
+                rec = Some(curr_rec);
             }
         }
-        return rec;
+        rec
+    }
+
+    fn occluded(&self, r: ray::Ray, t_min: f32, t_max: f32) -> bool {
+        self.list.iter().any(|obj| obj.occluded(r, t_min, t_max))
+    }
+
+    /// A list is the union of its objects.
+    fn spans(&self, r: ray::Ray) -> Option<Vec<Span<'_>>> {
+        let mut spans = Vec::new();
+        for obj in &self.list {
+            spans = csg::combine(csg::Operation::Union, spans, obj.spans(r)?);
+        }
+        Some(spans)
     }
 }
@@ -0,0 +1,176 @@
+use super::differential;
+use super::hitable;
+use super::ray;
+use super::render;
+use super::sampler::Sampler;
+use super::settings;
+use super::spectrum;
+use super::sphere;
+use super::vec3;
+
+/// Computes the value of a camera ray: radiance for the path tracers, or a
+/// false colour for the lookdev and debugging views. The differential is
+/// only used to filter textures.
+pub trait Integrator {
+    fn li(
+        &self,
+        r: ray::Ray,
+        differential: Option<differential::Differential>,
+        world: &dyn hitable::Hitable,
+        sampler: &mut dyn Sampler,
+    ) -> vec3::Vec3;
+}
+
+pub struct PathIntegrator {
+    pub limits: render::PathLimits,
+}
+
+impl Integrator for PathIntegrator {
+    fn li(
+        &self,
+        r: ray::Ray,
+        differential: Option<differential::Differential>,
+        world: &dyn hitable::Hitable,
+        sampler: &mut dyn Sampler,
+    ) -> vec3::Vec3 {
+        render::color(r, differential, world, &self.limits, sampler)
+    }
+}
+
+/// Traces wavelengths instead of RGB; see `render::color_spectral`.
+pub struct SpectralPathIntegrator {
+    pub limits: render::PathLimits,
+}
+
+impl Integrator for SpectralPathIntegrator {
+    fn li(
+        &self,
+        r: ray::Ray,
+        differential: Option<differential::Differential>,
+        world: &dyn hitable::Hitable,
+        sampler: &mut dyn Sampler,
+    ) -> vec3::Vec3 {
+        let wavelengths = spectrum::sample_wavelengths(sampler.next_1d());
+        let radiance = render::color_spectral(
+            r,
+            differential,
+            world,
+            &wavelengths,
+            &self.limits,
+            sampler,
+        );
+        spectrum::to_rgb(&wavelengths, &radiance)
+    }
+}
+
+/// White where the surface is open to the sky, darker where other geometry
+/// within `distance` blocks it. Rays that miss everything count as open.
+pub struct AmbientOcclusionIntegrator {
+    pub distance: f32,
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+    fn li(
+        &self,
+        r: ray::Ray,
+        _differential: Option<differential::Differential>,
+        world: &dyn hitable::Hitable,
+        sampler: &mut dyn Sampler,
+    ) -> vec3::Vec3 {
+        let rec = match world.hit(r, 0.001, f32::INFINITY) {
+            Some(rec) => rec,
+            None => return vec3::Vec3::new(1, 1, 1),
+        };
+        // The same cosine-weighted lobe as `Lambertian`.
+        let direction = rec.normal + sphere::random_in_unit_sphere(sampler);
+        let probe = ray::Ray {
+            a: rec.p,
+            b: direction,
+        };
+        // `direction` is not normalised, so scale the distance to match.
+        if world.occluded(probe, 0.001, self.distance / direction.length()) {
+            vec3::Vec3::new(0, 0, 0)
+        } else {
+            vec3::Vec3::new(1, 1, 1)
+        }
+    }
+}
+
+/// Shading normals of the first hit, mapped from [-1, 1] to [0, 1].
+pub struct NormalIntegrator;
+
+impl Integrator for NormalIntegrator {
+    fn li(
+        &self,
+        r: ray::Ray,
+        _differential: Option<differential::Differential>,
+        world: &dyn hitable::Hitable,
+        _sampler: &mut dyn Sampler,
+    ) -> vec3::Vec3 {
+        match world.hit(r, 0.001, f32::INFINITY) {
+            Some(rec) => 0.5 * (vec3::unit_vector(rec.normal) + vec3::Vec3::new(1, 1, 1)),
+            None => vec3::Vec3::new(0, 0, 0),
+        }
+    }
+}
+
+/// Distance along the ray to the first hit, from white at the camera fading
+/// to black at `range`.
+pub struct DepthIntegrator {
+    pub range: f32,
+}
+
+impl Integrator for DepthIntegrator {
+    fn li(
+        &self,
+        r: ray::Ray,
+        _differential: Option<differential::Differential>,
+        world: &dyn hitable::Hitable,
+        _sampler: &mut dyn Sampler,
+    ) -> vec3::Vec3 {
+        match world.hit(r, 0.001, f32::INFINITY) {
+            Some(rec) => {
+                let grey = (1.0 - rec.t * r.direction().length() / self.range).max(0.0);
+                vec3::Vec3::new(grey, grey, grey)
+            }
+            None => vec3::Vec3::new(0, 0, 0),
+        }
+    }
+}
+
+/// Surface coordinates of the first hit, as red for `u` and green for `v`.
+pub struct UvIntegrator;
+
+impl Integrator for UvIntegrator {
+    fn li(
+        &self,
+        r: ray::Ray,
+        _differential: Option<differential::Differential>,
+        world: &dyn hitable::Hitable,
+        _sampler: &mut dyn Sampler,
+    ) -> vec3::Vec3 {
+        match world.hit(r, 0.001, f32::INFINITY) {
+            Some(rec) => vec3::Vec3::new(rec.u, rec.v, 0.0),
+            None => vec3::Vec3::new(0, 0, 0),
+        }
+    }
+}
+
+pub fn from_settings(settings: &settings::RenderSettings) -> Box<dyn Integrator> {
+    match settings.integrator {
+        settings::IntegratorKind::Path if settings.spectral => Box::new(SpectralPathIntegrator {
+            limits: settings.path,
+        }),
+        settings::IntegratorKind::Path => Box::new(PathIntegrator {
+            limits: settings.path,
+        }),
+        settings::IntegratorKind::AmbientOcclusion => Box::new(AmbientOcclusionIntegrator {
+            distance: settings.ao_distance,
+        }),
+        settings::IntegratorKind::Normals => Box::new(NormalIntegrator),
+        settings::IntegratorKind::Uv => Box::new(UvIntegrator),
+        settings::IntegratorKind::Depth => Box::new(DepthIntegrator {
+            range: settings.depth_range,
+        }),
+    }
+}
@@ -0,0 +1,32 @@
+//! Path tracer behind the `raytracer-rs` binary. `render` draws a world into a
+//! `Frame`, and a `RenderControl` lets the caller watch its `Progress` and
+//! stop it early with a `CancelToken`.
+
+pub mod aov;
+pub mod aperture;
+pub mod bump;
+pub mod camera;
+pub mod checkpoint;
+pub mod csg;
+pub mod denoise;
+pub mod differential;
+pub mod film;
+pub mod hitable;
+pub mod integrator;
+pub mod material;
+pub mod output;
+pub mod pnm;
+pub mod preview;
+pub mod ray;
+pub mod render;
+pub mod sampler;
+pub mod settings;
+pub mod spectrum;
+pub mod sphere;
+pub mod stats;
+pub mod texture;
+pub mod tiles;
+pub mod tonemap;
+pub mod vec3;
+
+pub use render::{render, CancelToken, Progress, RenderControl};
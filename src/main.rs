@@ -1,29 +1,17 @@
-mod camera;
-mod hitable;
-mod material;
-mod ray;
-mod sphere;
-mod vec3;
-
-fn color(r: ray::Ray, world: &dyn hitable::Hitable, depth: i32) -> vec3::Vec3 {
-    let wrapped_rec = world.hit(r, 0.001, f32::INFINITY);
-    if !wrapped_rec.is_none() {
-        let rec = wrapped_rec.unwrap();
-        let (attenuation, scattered, scatter_bool) = rec.material.scatter(r, rec);
-        if depth < 50 && scatter_bool {
-            return attenuation * color(scattered, world, depth + 1);
-        } else {
-            return vec3::Vec3::new(0, 0, 0);
-        }
-    } else {
-        let unit_direction = vec3::unit_vector(r.direction());
-        let t = 0.5 * (unit_direction.y() + 1.0);
-        return (1.0 - t) * vec3::Vec3::new(1, 1, 1)
-            + t * vec3::Vec3::new(0.5, 0.7, 1.0);
-    }
-}
+use raytracer_rs::{
+    aperture, bump, camera, checkpoint, csg, denoise, hitable, material, output, preview, render,
+    sampler, settings, sphere, stats, texture, tonemap, vec3,
+};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sampler::Sampler;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-fn generate_world() -> Vec<Box<dyn hitable::Hitable>>{
+fn generate_world(rng: &mut StdRng) -> Vec<Box<dyn hitable::Hitable>>{
 
     let mut objs = Vec::new();
 
@@ -39,10 +27,10 @@ fn generate_world() -> Vec<Box<dyn hitable::Hitable>>{
 
     for a in -11..11 {
         for b in -11..11 {
-            let choose_mat = rand::random::<f32>();
+            let choose_mat = rng.gen::<f32>();
             let center = vec3::Vec3::new(
-                    (a as f32)+0.9*rand::random::<f32>(), 0.2,
-                    (b as f32)+0.9*rand::random::<f32>()
+                    (a as f32)+0.9*rng.gen::<f32>(), 0.2,
+                    (b as f32)+0.9*rng.gen::<f32>()
             );
 
             let base = vec3::Vec3::new(4, 0.2, 0);
@@ -51,22 +39,23 @@ fn generate_world() -> Vec<Box<dyn hitable::Hitable>>{
                 let mat: Box<dyn material::Material>;
                 if choose_mat < 0.8 {
                     mat = Box::new(material::Lambertian {
-                        albedo: vec3::Vec3::new(rand::random::<f32>()*rand::random::<f32>(), rand::random::<f32>()*rand::random::<f32>(), rand::random::<f32>()*rand::random::<f32>()),
+                        albedo: vec3::Vec3::new(rng.gen::<f32>()*rng.gen::<f32>(), rng.gen::<f32>()*rng.gen::<f32>(), rng.gen::<f32>()*rng.gen::<f32>()),
                     }) as Box<dyn material::Material>;
                 } else if choose_mat < 0.95 {
                     mat = Box::new(material::Metal {
-                            albedo: vec3::Vec3::new(1.0 + rand::random::<f32>(), 0.5*(1.0+rand::random::<f32>()), 0.5*rand::random::<f32>()),
-                            fuzz: 0.5*rand::random::<f32>(),
+                            albedo: vec3::Vec3::new(1.0 + rng.gen::<f32>(), 0.5*(1.0+rng.gen::<f32>()), 0.5*rng.gen::<f32>()),
+                            fuzz: 0.5*rng.gen::<f32>(),
                         }) as Box<dyn material::Material>;
                 } else {
                     mat = Box::new(material::Dielectric {
                             refraction_index: 1.7,
+                            dispersion: material::Dispersion::None,
                         }) as Box<dyn material::Material>;
                 }
 
 
                 objs.push(Box::new(sphere::Sphere {
-                    center: center,
+                    center,
                     radius: 0.2,
                     material: mat,
                 }) as Box<dyn hitable::Hitable>);
@@ -97,6 +86,7 @@ fn generate_world() -> Vec<Box<dyn hitable::Hitable>>{
 
     let mat = Box::new(material::Dielectric {
         refraction_index: 1.7,
+        dispersion: material::Dispersion::None,
     }) as Box<dyn material::Material>;
 
     objs.push(Box::new(sphere::Sphere {
@@ -105,105 +95,640 @@ fn generate_world() -> Vec<Box<dyn hitable::Hitable>>{
         material: mat,
     }) as Box<dyn hitable::Hitable>);
 
-    return objs;
+    objs
 }
 
-fn main() {
-    let nx = 200;
-    let ny = 100;
-    let ns = 10;
+fn generate_dispersion_world() -> Vec<Box<dyn hitable::Hitable>> {
+    let mut objs = Vec::new();
 
-    print!("P3\n{} {} \n255\n", nx, ny);
+    let mat = Box::new(material::Lambertian {
+        albedo: vec3::Vec3::new(0.5, 0.5, 0.5),
+    }) as Box<dyn material::Material>;
+    objs.push(Box::new(sphere::Sphere {
+        center: vec3::Vec3::new(0, -1000, 0),
+        radius: 1000.0,
+        material: mat,
+    }) as Box<dyn hitable::Hitable>);
+
+    // A row of saturated spheres behind the glass gives the dispersion some
+    // edges to split.
+    for a in -4..5 {
+        let hue = (a + 4) as f32 / 9.0;
+        let albedo = vec3::Vec3::new(
+            (1.5 - (6.0 * hue - 3.0).abs()).clamp(0.05, 0.9),
+            (2.0 - (6.0 * hue - 2.0).abs()).clamp(0.05, 0.9),
+            (2.0 - (6.0 * hue - 4.0).abs()).clamp(0.05, 0.9),
+        );
+        let mat = Box::new(material::Lambertian { albedo }) as Box<dyn material::Material>;
+        objs.push(Box::new(sphere::Sphere {
+            center: vec3::Vec3::new(-2.0 + a as f32 * 0.6, 0.3, -3.0 - a as f32 * 0.6),
+            radius: 0.3,
+            material: mat,
+        }) as Box<dyn hitable::Hitable>);
+    }
+
+    let glasses = [
+        (-2.2, 1.5, material::Dispersion::BK7),
+        (0.0, 2.42, material::Dispersion::DIAMOND),
+        // Exaggerated flint glass.
+        (2.2, 1.7, material::Dispersion::Cauchy { b: 0.08 }),
+    ];
+    for &(x, refraction_index, dispersion) in glasses.iter() {
+        let mat = Box::new(material::Dielectric {
+            refraction_index,
+            dispersion,
+        }) as Box<dyn material::Material>;
+        objs.push(Box::new(sphere::Sphere {
+            center: vec3::Vec3::new(x, 1, 0),
+            radius: 1.0,
+            material: mat,
+        }) as Box<dyn hitable::Hitable>);
+    }
+
+    objs
+}
+
+/// Two-by-two checkerboard, one repeat of the textured scene's default
+/// texture.
+fn checkerboard() -> texture::Mipmap {
+    let size = 64;
+    let (light, dark) = (vec3::Vec3::new(0.8, 0.8, 0.8), vec3::Vec3::new(0.1, 0.1, 0.1));
+    let texels = (0..size * size)
+        .map(|i| {
+            if (i % size < size / 2) == (i / size < size / 2) {
+                light
+            } else {
+                dark
+            }
+        })
+        .collect();
+    texture::Mipmap::new(size, size, texels)
+}
+
+/// Round dimples, one per repeat, as the default height map of the textured
+/// scene's mirror sphere.
+fn dimples() -> texture::Mipmap {
+    let size = 32;
+    let texels = (0..size * size)
+        .map(|i| {
+            let x = (i % size) as f32 / size as f32 - 0.5;
+            let y = (i / size) as f32 / size as f32 - 0.5;
+            let h = (4.0 * (x * x + y * y)).min(1.0);
+            vec3::Vec3::new(h, h, h)
+        })
+        .collect();
+    texture::Mipmap::new(size, size, texels)
+}
+
+fn load_map(path: &str) -> Arc<texture::Mipmap> {
+    Arc::new(texture::Mipmap::load_data(path).unwrap_or_else(|e| {
+        eprintln!("cannot load {}: {}", path, e);
+        std::process::exit(1);
+    }))
+}
+
+/// Normal or height map for the textured scene's mirror sphere.
+fn shading_map(settings: &settings::RenderSettings) -> bump::ShadingMap {
+    let texture = |mipmap| texture::ImageTexture {
+        mipmap,
+        repeat: (24.0, 12.0),
+        filter: texture::TextureFilter::Bilinear,
+    };
+    if let Some(path) = &settings.normal_map {
+        return bump::ShadingMap::Normal(texture(load_map(path)));
+    }
+    let mipmap = match &settings.bump_map {
+        Some(path) => load_map(path),
+        None => Arc::new(dimples()),
+    };
+    bump::ShadingMap::Height {
+        texture: texture(mipmap),
+        scale: settings.bump_scale,
+    }
+}
+
+fn generate_textured_world(
+    mipmap: texture::Mipmap,
+    filter: texture::TextureFilter,
+    map: bump::ShadingMap,
+) -> Vec<Box<dyn hitable::Hitable>> {
+    let mipmap = Arc::new(mipmap);
+    let textured = |repeat: (f32, f32)| {
+        Box::new(material::TexturedLambertian {
+            texture: texture::ImageTexture {
+                mipmap: mipmap.clone(),
+                repeat,
+                filter,
+            },
+        }) as Box<dyn material::Material>
+    };
+    vec![
+        // The floor is the top of a large sphere. Its pole is below the look-at
+        // point, so the checks become rings of wedges, each about two metres deep
+        // and shrinking towards the horizon.
+        Box::new(sphere::Sphere {
+            center: vec3::Vec3::new(0, -1000, 0),
+            radius: 1000.0,
+            material: textured((64.0, 1500.0)),
+        }) as Box<dyn hitable::Hitable>,
+        Box::new(sphere::Sphere {
+            center: vec3::Vec3::new(-4, 1, 0),
+            radius: 1.0,
+            material: textured((8.0, 4.0)),
+        }) as Box<dyn hitable::Hitable>,
+        Box::new(sphere::Sphere {
+            center: vec3::Vec3::new(0, 1, 0),
+            radius: 1.0,
+            material: Box::new(material::Dielectric {
+                refraction_index: 1.5,
+                dispersion: material::Dispersion::None,
+            }),
+        }) as Box<dyn hitable::Hitable>,
+        Box::new(bump::Bumped {
+            object: Box::new(sphere::Sphere {
+                center: vec3::Vec3::new(4, 1, 0),
+                radius: 1.0,
+                material: Box::new(material::Metal {
+                    albedo: vec3::Vec3::new(0.9, 0.9, 0.9),
+                    fuzz: 0.0,
+                }),
+            }),
+            map,
+        }) as Box<dyn hitable::Hitable>,
+    ]
+}
+
+fn generate_csg_world() -> Vec<Box<dyn hitable::Hitable>> {
+    let sphere = |center: vec3::Vec3, radius: f32, material: Box<dyn material::Material>| {
+        Box::new(sphere::Sphere {
+            center,
+            radius,
+            material,
+        }) as Box<dyn hitable::Hitable>
+    };
+    let glass = || {
+        Box::new(material::Dielectric {
+            refraction_index: 1.5,
+            dispersion: material::Dispersion::None,
+        }) as Box<dyn material::Material>
+    };
+    let diffuse = |r: f32, g: f32, b: f32| {
+        Box::new(material::Lambertian {
+            albedo: vec3::Vec3::new(r, g, b),
+        }) as Box<dyn material::Material>
+    };
+    let solid = |operation, left, right| {
+        Box::new(csg::Csg::new(operation, left, right).expect("spheres are closed"))
+            as Box<dyn hitable::Hitable>
+    };
+
+    vec![
+        sphere(vec3::Vec3::new(0, -1000, 0), 1000.0, diffuse(0.5, 0.5, 0.5)),
+        // A hollow glass sphere with walls a tenth of its radius thick.
+        solid(
+            csg::Operation::Difference,
+            sphere(vec3::Vec3::new(0, 1, 0), 1.0, glass()),
+            sphere(vec3::Vec3::new(0, 1, 0), 0.9, glass()),
+        ),
+        // A biconvex lens where two spheres overlap.
+        solid(
+            csg::Operation::Intersection,
+            sphere(vec3::Vec3::new(1.2, 0.8, 2.5), 1.0, glass()),
+            sphere(vec3::Vec3::new(2.8, 0.8, 2.5), 1.0, glass()),
+        ),
+        // A red ball with a bite taken out of it, lined in yellow.
+        solid(
+            csg::Operation::Difference,
+            sphere(vec3::Vec3::new(-4, 1, 0), 1.0, diffuse(0.7, 0.1, 0.1)),
+            sphere(vec3::Vec3::new(-3.4, 1.6, 0.9), 0.8, diffuse(0.8, 0.7, 0.1)),
+        ),
+        // Two merged metal balls.
+        solid(
+            csg::Operation::Union,
+            sphere(
+                vec3::Vec3::new(4, 0.8, 0),
+                0.8,
+                Box::new(material::Metal {
+                    albedo: vec3::Vec3::new(0.7, 0.6, 0.5),
+                    fuzz: 0.0,
+                }),
+            ),
+            sphere(
+                vec3::Vec3::new(4, 1.9, 0),
+                0.5,
+                Box::new(material::Metal {
+                    albedo: vec3::Vec3::new(0.7, 0.6, 0.5),
+                    fuzz: 0.0,
+                }),
+            ),
+        ),
+    ]
+}
+
+fn make_sampler(kind: settings::SamplerKind, seed: u64, ns: i32) -> Box<dyn Sampler> {
+    match kind {
+        settings::SamplerKind::Random => Box::new(sampler::RandomSampler::new(seed)),
+        settings::SamplerKind::Stratified => Box::new(sampler::StratifiedSampler::new(seed, ns)),
+        settings::SamplerKind::Halton => Box::new(sampler::HaltonSampler::new(seed)),
+        settings::SamplerKind::Sobol => Box::new(sampler::SobolSampler::new(seed)),
+    }
+}
+
+/// Makes the first Ctrl-C stop the render early, so that it still writes its
+/// image and checkpoint. A second one exits straight away.
+#[cfg(unix)]
+fn cancel_on_interrupt(cancel: render::CancelToken) {
+    use std::sync::OnceLock;
+
+    static INTERRUPT: OnceLock<render::CancelToken> = OnceLock::new();
+    extern "C" fn handle(_: libc::c_int) {
+        match INTERRUPT.get() {
+            Some(cancel) if !cancel.is_cancelled() => cancel.cancel(),
+            _ => unsafe { libc::_exit(130) },
+        }
+    }
+    let _ = INTERRUPT.set(cancel);
+    unsafe {
+        libc::signal(libc::SIGINT, handle as *const () as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+fn cancel_on_interrupt(_cancel: render::CancelToken) {}
+
+/// Records the time since `start` as the named phase and starts the next one.
+fn end_phase(name: &'static str, start: &mut Instant) {
+    stats::record_phase(name, start.elapsed());
+    *start = Instant::now();
+}
+
+/// Shortest time between redraws of the terminal preview.
+const PREVIEW_INTERVAL: Duration = Duration::from_millis(250);
+
+fn status_line(progress: &render::Progress) -> String {
+    let seconds = progress.elapsed.as_secs_f64();
+    let mut line = format!(
+        "pass {}  {:5.1}%  {:.2} Mrays/s  noise {:.4}  {:.1}s",
+        progress.passes,
+        100.0 * progress.samples as f64 / progress.total_samples.max(1) as f64,
+        progress.rays as f64 / seconds.max(1e-3) / 1e6,
+        progress.noise,
+        seconds
+    );
+    if let Some(eta) = progress.eta {
+        line.push_str(&format!("  ETA {:.0}s", eta.as_secs_f64()));
+    }
+    line
+}
+
+/// Reads the checkpoints to resume from and merges them into one.
+fn load_checkpoints(
+    settings: &settings::RenderSettings,
+) -> Result<Option<checkpoint::Checkpoint>, String> {
+    let mut merged: Option<checkpoint::Checkpoint> = None;
+    for path in &settings.resume {
+        let checkpoint = checkpoint::Checkpoint::read(path)
+            .map_err(|e| format!("cannot read checkpoint {}: {}", path, e))?;
+        match merged.as_mut() {
+            Some(merged) => merged
+                .merge(&checkpoint)
+                .map_err(|e| format!("cannot merge {}: {}", path, e))?,
+            None => merged = Some(checkpoint),
+        }
+    }
+    if let Some(checkpoint) = &merged {
+        checkpoint.check_resumable(settings)?;
+    }
+    Ok(merged)
+}
+
+/// Prints the RMS error of every sampler at the configured sample count,
+/// against a reference rendered with `reference_samples` Sobol samples.
+fn measure_noise(
+    cam: &dyn camera::Camera,
+    world: &dyn hitable::Hitable,
+    settings: &settings::RenderSettings,
+    reference_samples: i32,
+) {
+    let ns = settings.ns;
+    let mut reference_settings = settings.clone();
+    reference_settings.ns = reference_samples;
+    reference_settings.noise_threshold = None;
+    // A different seed keeps the reference independent of the measured renders.
+    let mut reference_sampler = sampler::SobolSampler::new(settings.seed ^ 0x5eed);
+    let reference = render::render(
+        cam,
+        world,
+        &mut reference_sampler,
+        &reference_settings,
+        None,
+        Default::default(),
+    )
+    .pixels;
 
-    let objs = generate_world();
-
-
-    // let objs = vec![
-    //     Box::new(sphere::Sphere {
-    //         center: vec3::Vec3 {
-    //             e: [0.0, 0.0, -1.0],
-    //         },
-    //         radius: 0.5,
-    //         material: &material::Lambertian {
-    //             albedo: vec3::Vec3 { e: [0.1, 0.2, 0.5] },
-    //         },
-    //     }) as Box<dyn hitable::Hitable>,
-    //     Box::new(sphere::Sphere {
-    //         center: vec3::Vec3 {
-    //             e: [0.0, -100.5, -1.0],
-    //         },
-    //         radius: 100.0,
-    //         material: &material::Lambertian {
-    //             albedo: vec3::Vec3 { e: [0.8, 0.8, 0.0] },
-    //         },
-    //     }) as Box<dyn hitable::Hitable>,
-    //     Box::new(sphere::Sphere {
-    //         center: vec3::Vec3 {
-    //             e: [1.0, 0.0, -1.0],
-    //         },
-    //         radius: 0.5,
-    //         material: &material::Metal {
-    //             albedo: vec3::Vec3 { e: [0.8, 0.6, 0.2] },
-    //             fuzz: 0.01,
-    //         },
-    //     }) as Box<dyn hitable::Hitable>,
-    //     Box::new(sphere::Sphere {
-    //         center: vec3::Vec3 {
-    //             e: [-1.0, 0.0, -1.0],
-    //         },
-    //         radius: 0.5,
-    //         material: &material::Dielectric {
-    //             refraction_index: 1.7,
-    //         },
-    //     }) as Box<dyn hitable::Hitable>,
-    //     Box::new(sphere::Sphere {
-    //         center: vec3::Vec3 {
-    //             e: [-1.0, 0.0, -1.0],
-    //         },
-    //         radius: -0.45,
-    //         material: &material::Dielectric {
-    //             refraction_index: 1.7,
-    //         },
-    //     }) as Box<dyn hitable::Hitable>,
-    // ];
+    println!("sampler     spp  rmse      vs random");
+    let mut random_rmse = 0.0;
+    for (name, kind) in settings::SamplerKind::ALL.iter() {
+        let mut sampler = make_sampler(*kind, settings.seed, ns);
+        let pixels = render::render(
+            cam,
+            world,
+            sampler.as_mut(),
+            settings,
+            None,
+            Default::default(),
+        )
+        .pixels;
+        let squared_error: f64 = pixels
+            .iter()
+            .zip(reference.iter())
+            .map(|(&p, &r)| (p - r).squared_length() as f64 / 3.0)
+            .sum();
+        let rmse = (squared_error / pixels.len() as f64).sqrt();
+        if *kind == settings::SamplerKind::Random {
+            random_rmse = rmse;
+        }
+        println!(
+            "{:<10} {:>4}  {:.6}  {:.2}x",
+            name,
+            ns,
+            rmse,
+            random_rmse / rmse
+        );
+    }
+}
 
+/// Writes the per-pixel sample counts as a false-colour PPM, from blue for the
+/// fewest samples to red for the most.
+fn write_heatmap(path: &str, frame: &render::Frame) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P3\n{} {} \n255\n", frame.width, frame.height)?;
+    let max = frame.sample_counts.iter().copied().max().unwrap_or(1).max(1);
+    for &count in frame.sample_counts.iter() {
+        let t = count as f32 / max as f32;
+        let col = vec3::Vec3::new(
+            (1.5 - (4.0 * t - 3.0).abs()).clamp(0.0, 1.0),
+            (1.5 - (4.0 * t - 2.0).abs()).clamp(0.0, 1.0),
+            (1.5 - (4.0 * t - 1.0).abs()).clamp(0.0, 1.0),
+        ) * 255.99;
+        writeln!(out, "{} {} {}", col.r() as i32, col.g() as i32, col.b() as i32)?;
+    }
+    Ok(())
+}
+
+fn aperture_shape(settings: &settings::RenderSettings) -> aperture::Aperture {
+    if let Some(path) = &settings.aperture_mask {
+        aperture::Aperture::Mask(aperture::ApertureMask::load_pgm(path).unwrap_or_else(|e| {
+            eprintln!("cannot load aperture mask {}: {}", path, e);
+            std::process::exit(1);
+        }))
+    } else if let Some(blades) = settings.aperture_blades {
+        aperture::Aperture::Polygon {
+            blades,
+            rotation: settings.aperture_rotation,
+        }
+    } else {
+        aperture::Aperture::Circle
+    }
+}
+
+fn main() {
+    let settings = settings::RenderSettings::from_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let nx = settings.nx;
+    let ny = settings.ny;
+    let ns = settings.ns;
+    if settings.stats || settings.stats_json.is_some() {
+        stats::enable();
+    }
+    let mut phase = Instant::now();
+
+    let scene_seed = settings.scene_seed.unwrap_or(settings.seed);
+    let objs = match settings.scene {
+        settings::Scene::Random => generate_world(&mut StdRng::seed_from_u64(scene_seed)),
+        settings::Scene::Dispersion => generate_dispersion_world(),
+        settings::Scene::Textured => {
+            let mipmap = match &settings.texture {
+                Some(path) => texture::Mipmap::load(path).unwrap_or_else(|e| {
+                    eprintln!("cannot load texture {}: {}", path, e);
+                    std::process::exit(1);
+                }),
+                None => checkerboard(),
+            };
+            generate_textured_world(mipmap, settings.texture_filter, shading_map(&settings))
+        }
+        settings::Scene::Csg => generate_csg_world(),
+    };
 
     let world = hitable::HitableList { list: objs };
+    end_phase("scene", &mut phase);
 
 
     let lookfrom = vec3::Vec3::new(5, 2, 4);
     let lookat = vec3::Vec3::new(0, 0, -1);
-    let cam = Box::new(camera::NoBlurCamera::new(
-        lookfrom,
-        lookat,
-        vec3::Vec3 { e: [0.0, 1.0, 0.0] },
-        50.0,
-        nx as f32 / ny as f32,
-        // 2.0,
-        // (lookfrom-lookat).length()
-    )) as Box<dyn camera::Camera>;
-
-    for j in (0..ny).rev() {
-        for i in 0..nx {
-            let mut col = vec3::Vec3::new(0, 0, 0);
-            for _s in 0..ns {
-                let u = (i as f32 + rand::random::<f32>()) / nx as f32;
-                let v = (j as f32 + rand::random::<f32>()) / ny as f32;
-
-                let r = cam.get_ray(u, v);
-                col += color(r, &world, 0);
-            }
-            col /= ns as f32;
-            col = vec3::Vec3 {
-                e: [col.r().sqrt(), col.g().sqrt(), col.b().sqrt()],
+    let vup = vec3::Vec3 { e: [0.0, 1.0, 0.0] };
+    let aspect = nx as f32 / ny as f32;
+    let focus_dist = |vfov: f32| match settings.focus {
+        settings::Focus::LookAt => (lookfrom - lookat).length(),
+        settings::Focus::Distance(d) => d,
+        settings::Focus::Auto(pixel) => {
+            let (s, t) = match pixel {
+                Some((x, y)) => (
+                    (x as f32 + 0.5) / nx as f32,
+                    1.0 - (y as f32 + 0.5) / ny as f32,
+                ),
+                None => (0.5, 0.5),
             };
-            let icol = col * 255.99;
-            println!(
-                "{} {} {}",
-                icol.r() as i32,
-                icol.g() as i32,
-                icol.b() as i32
-            );
+            camera::autofocus(&world, lookfrom, lookat, vup, vfov, aspect, s, t)
+                .unwrap_or_else(|| {
+                    eprintln!("autofocus ray hit nothing, focusing on the look-at point");
+                    (lookfrom - lookat).length()
+                })
+        }
+    };
+    let cam: Box<dyn camera::Camera> = match settings.camera {
+        settings::CameraKind::NoBlur => Box::new(camera::NoBlurCamera::new(
+            lookfrom, lookat, vup, 50.0, aspect,
+        )),
+        settings::CameraKind::DefocusBlur => Box::new(
+            camera::DefocusBlurCamera::new(
+                lookfrom,
+                lookat,
+                vup,
+                50.0,
+                aspect,
+                2.0,
+                focus_dist(50.0),
+            )
+            .with_aperture(aperture_shape(&settings))
+            .with_vignetting(settings.vignetting),
+        ),
+        settings::CameraKind::Orthographic => Box::new(camera::OrthographicCamera::new(
+            lookfrom, lookat, vup, 6.0, aspect,
+        )),
+        settings::CameraKind::Fisheye => Box::new(camera::FisheyeCamera::new(
+            lookfrom, lookat, vup, 180.0, aspect,
+        )),
+        settings::CameraKind::Equirectangular => Box::new(
+            camera::EquirectangularCamera::new(lookfrom, lookat, vup),
+        ),
+        settings::CameraKind::Stereo => Box::new(camera::StereoCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            50.0,
+            aspect,
+            settings.interocular,
+            settings.convergence.unwrap_or((lookfrom - lookat).length()),
+            settings.stereo_layout,
+        )),
+        settings::CameraKind::Ods => Box::new(camera::OdsCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            settings.interocular,
+            settings.stereo_layout,
+        )),
+        settings::CameraKind::Physical => Box::new(
+            settings
+                .physical
+                .camera(
+                    lookfrom,
+                    lookat,
+                    vup,
+                    aspect,
+                    focus_dist(settings.physical.vfov(aspect)),
+                )
+                .with_aperture(aperture_shape(&settings))
+                .with_vignetting(settings.vignetting),
+        ),
+    };
+    let camera_exposure = if settings.camera == settings::CameraKind::Physical {
+        settings.physical.exposure()
+    } else {
+        1.0
+    };
+    let pipeline = tonemap::Pipeline {
+        exposure: camera_exposure * settings.exposure.exp2(),
+        tone_map: settings.tone_map,
+        dither: settings.dither,
+    };
+
+    if let Some(reference_samples) = settings.measure_noise {
+        measure_noise(cam.as_ref(), &world, &settings, reference_samples);
+        return;
+    }
+
+    end_phase("camera", &mut phase);
+
+    let resume = load_checkpoints(&settings).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let cancel = render::CancelToken::new();
+    cancel_on_interrupt(cancel.clone());
+    let mut preview = if settings.preview {
+        let columns = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok());
+        Some(preview::Preview::new(columns.unwrap_or(80)))
+    } else {
+        None
+    };
+    let mut last_preview: Option<Instant> = None;
+    let mut status = String::new();
+    let mut report = |progress: &render::Progress| {
+        if let Some(preview) = preview.as_mut() {
+            if last_preview.is_none_or(|t| t.elapsed() >= PREVIEW_INTERVAL) {
+                let pixels = progress.film.pixels();
+                let _ = preview.draw(&mut std::io::stderr(), &pixels, nx, ny, &pipeline);
+                last_preview = Some(Instant::now());
+            }
+        }
+        status = status_line(progress);
+        eprint!("\r\x1b[K{}", status);
+    };
+    let control = render::RenderControl {
+        cancel: Some(cancel),
+        progress: if settings.progress || settings.preview {
+            Some(&mut report)
+        } else {
+            None
+        },
+    };
+    let mut sampler = make_sampler(settings.sampler, settings.seed, ns);
+    let mut frame = render::render(
+        cam.as_ref(),
+        &world,
+        sampler.as_mut(),
+        &settings,
+        resume,
+        control,
+    );
+    // The last pass may not have been drawn yet.
+    if let Some(preview) = preview.as_mut() {
+        let _ = preview.draw(
+            &mut std::io::stderr(),
+            &frame.pixels,
+            frame.width,
+            frame.height,
+            &pipeline,
+        );
+        eprintln!("{}", status);
+    } else if settings.progress {
+        eprintln!();
+    }
+    end_phase("render", &mut phase);
+    // Cropping changes the size of the output.
+    let (nx, ny) = (frame.width, frame.height);
+
+    if let Some(aovs) = &frame.aovs {
+        if let Some(dir) = &settings.aov_dir {
+            if let Err(e) = output::write_aov_pfms(dir, nx, ny, aovs) {
+                eprintln!("cannot write AOVs to {}: {}", dir, e);
+            }
+        }
+        if let Some(path) = &settings.aov_exr {
+            let beauty: Vec<vec3::Vec3> =
+                frame.pixels.iter().map(|&p| p * pipeline.exposure).collect();
+            if let Err(e) = output::write_aov_exr(path, nx, ny, &beauty, aovs) {
+                eprintln!("cannot write {}: {}", path, e);
+            }
+        }
+    }
+
+    // AOV files keep the raw beauty pass so that compositors can denoise it
+    // their own way.
+    if settings.denoise {
+        if let Some(aovs) = &frame.aovs {
+            frame.pixels = denoise::denoise(nx, ny, &frame.pixels, aovs);
+        }
+        end_phase("denoise", &mut phase);
+    }
+
+    if let Some(path) = &settings.heatmap {
+        if let Err(e) = write_heatmap(path, &frame) {
+            eprintln!("cannot write heatmap {}: {}", path, e);
+        }
+    }
+
+    print!("P3\n{} {} \n255\n", nx, ny);
+
+    for (index, &col) in frame.pixels.iter().enumerate() {
+        let x = index as i32 % nx;
+        let y = index as i32 / nx;
+        let [r, g, b] = pipeline.quantize(col, x, y);
+        println!("{} {} {}", r, g, b);
+    }
+    end_phase("output", &mut phase);
+
+    let counters = stats::collect();
+    if settings.stats {
+        eprint!("{}", counters.table());
+    }
+    if let Some(path) = &settings.stats_json {
+        if let Err(e) = std::fs::write(path, counters.json()) {
+            eprintln!("cannot write {}: {}", path, e);
         }
     }
 }
@@ -1,10 +1,50 @@
 use super::hitable;
 use super::ray;
+use super::sampler;
 use super::sphere;
+use super::texture;
 use super::vec3;
 
 pub trait Material {
-    fn scatter(&self, r_in: ray::Ray, rec: hitable::HitRecord) -> (vec3::Vec3, ray::Ray, bool);
+    fn scatter(
+        &self,
+        r_in: ray::Ray,
+        rec: hitable::HitRecord,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (vec3::Vec3, ray::Ray, bool);
+
+    /// Surface colour under uniform white light, written to the albedo AOV.
+    fn albedo(&self) -> vec3::Vec3;
+
+    /// Scatters a single wavelength, in nanometres, of a spectral path. Only
+    /// materials whose behaviour depends on the wavelength override this.
+    fn scatter_spectral(
+        &self,
+        r_in: ray::Ray,
+        rec: hitable::HitRecord,
+        _wavelength: f32,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (vec3::Vec3, ray::Ray, bool) {
+        self.scatter(r_in, rec, sampler)
+    }
+
+    /// Whether the direction picked by `scatter_spectral` depends on the
+    /// wavelength, so that a path can no longer carry several wavelengths.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+
+    /// Whether scattered rays follow a (possibly blurred) mirror or refraction
+    /// direction rather than a diffuse lobe.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// Whether scattered rays follow the exact mirror or refraction
+    /// direction, with no blur, so that ray differentials can follow them.
+    fn is_perfectly_specular(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -13,17 +53,57 @@ pub struct Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: ray::Ray, rec: hitable::HitRecord) -> (vec3::Vec3, ray::Ray, bool) {
-        let target = rec.p + rec.normal + sphere::random_in_unit_sphere();
+    fn scatter(
+        &self,
+        _r_in: ray::Ray,
+        rec: hitable::HitRecord,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (vec3::Vec3, ray::Ray, bool) {
+        let target = rec.p + rec.normal + sphere::random_in_unit_sphere(sampler);
+        let mut direction = target - rec.p;
+        // A bumped shading normal can send the ray into the surface; mirror
+        // it back out rather than let it leak through.
+        let below = direction.dot(rec.geometric_normal);
+        if below < 0.0 {
+            direction -= 2.0 * below * rec.geometric_normal;
+        }
         (
             self.albedo,
             ray::Ray {
                 a: rec.p,
-                b: target - rec.p,
+                b: direction,
             },
             true,
         )
     }
+
+    fn albedo(&self) -> vec3::Vec3 {
+        self.albedo
+    }
+}
+
+/// Lambertian surface whose albedo comes from an image texture.
+#[derive(Clone)]
+pub struct TexturedLambertian {
+    pub texture: texture::ImageTexture,
+}
+
+impl Material for TexturedLambertian {
+    fn scatter(
+        &self,
+        r_in: ray::Ray,
+        rec: hitable::HitRecord,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (vec3::Vec3, ray::Ray, bool) {
+        Lambertian {
+            albedo: self.texture.value(&rec),
+        }
+        .scatter(r_in, rec, sampler)
+    }
+
+    fn albedo(&self) -> vec3::Vec3 {
+        self.texture.mipmap.average()
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -39,23 +119,91 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: ray::Ray, rec: hitable::HitRecord) -> (vec3::Vec3, ray::Ray, bool) {
+    fn scatter(
+        &self,
+        r_in: ray::Ray,
+        rec: hitable::HitRecord,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (vec3::Vec3, ray::Ray, bool) {
         let reflected = self.reflect(vec3::unit_vector(r_in.direction()), rec.normal);
         let scattered = ray::Ray {
             a: rec.p,
-            b: reflected + self.fuzz * sphere::random_in_unit_sphere(),
+            b: reflected + self.fuzz * sphere::random_in_unit_sphere(sampler),
         };
+        // Absorb rays that leave below either the shading normal or, when
+        // it is bumped, the actual surface.
         (
             self.albedo,
             scattered,
-            (scattered.direction().dot(rec.normal)) > 0.0,
+            (scattered.direction().dot(rec.normal)) > 0.0
+                && scattered.direction().dot(rec.geometric_normal) > 0.0,
         )
     }
+
+    fn albedo(&self) -> vec3::Vec3 {
+        self.albedo
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn is_perfectly_specular(&self) -> bool {
+        self.fuzz == 0.0
+    }
+}
+
+/// Wavelength dependence of a dielectric's index of refraction.
+#[derive(Copy, Clone)]
+pub enum Dispersion {
+    None,
+    /// Cauchy's equation n = A + B / lambda^2, with `b` in square micrometres
+    /// and A chosen so that `refraction_index` holds at the sodium d-line.
+    Cauchy { b: f32 },
+    /// Sellmeier equation with wavelengths in micrometres. This gives the index
+    /// directly; `refraction_index` is then only used by RGB renders.
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+/// Wavelength of the sodium d-line in nanometres, where glasses are usually
+/// specified.
+const D_LINE: f32 = 587.6;
+
+impl Dispersion {
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612, 0.231_792_34, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+
+    pub const DIAMOND: Dispersion = Dispersion::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030_625, 0.011_236, 0.0],
+    };
+
+    fn index(&self, refraction_index: f32, wavelength: f32) -> f32 {
+        let micrometres = wavelength / 1000.0;
+        match self {
+            Dispersion::None => refraction_index,
+            Dispersion::Cauchy { b } => {
+                let d = D_LINE / 1000.0;
+                refraction_index - b / (d * d) + b / (micrometres * micrometres)
+            }
+            Dispersion::Sellmeier { b, c } => {
+                let l2 = micrometres * micrometres;
+                let mut n2 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * l2 / (l2 - c[i]);
+                }
+                n2.max(1.0).sqrt()
+            }
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
 pub struct Dielectric {
     pub refraction_index: f32,
+    pub dispersion: Dispersion,
 }
 
 impl Dielectric {
@@ -69,41 +217,43 @@ impl Dielectric {
         let dt = uv.dot(n);
         let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
         if discriminant > 0.0 {
-            return Some(ni_over_nt * (uv - n * dt) - n * discriminant.sqrt());
+            Some(ni_over_nt * (uv - n * dt) - n * discriminant.sqrt())
         } else {
-            return None;
+            None
         }
     }
 
-    fn schlick(&self, cosine: f32) -> f32 {
-        let r0 = ((1.0 - self.refraction_index) / (1.0 + self.refraction_index)).powi(2);
+    fn schlick(&self, cosine: f32, refraction_index: f32) -> f32 {
+        let r0 = ((1.0 - refraction_index) / (1.0 + refraction_index)).powi(2);
         r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
     }
-}
 
-impl Material for Dielectric {
-    fn scatter(&self, r_in: ray::Ray, rec: hitable::HitRecord) -> (vec3::Vec3, ray::Ray, bool) {
+    fn scatter_with_index(
+        &self,
+        r_in: ray::Ray,
+        rec: hitable::HitRecord,
+        refraction_index: f32,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (vec3::Vec3, ray::Ray, bool) {
         let attenuation = vec3::Vec3 { e: [1.0, 1.0, 1.0] };
 
-        let outward_normal: vec3::Vec3;
-        let ni_over_nt: f32;
-        let cosine: f32;
-
-        if r_in.direction().dot(rec.normal) > 0.0 {
-            outward_normal = -rec.normal;
-            ni_over_nt = self.refraction_index;
-            cosine = self.refraction_index * r_in.direction().dot(rec.normal)
-                / r_in.direction().length();
+        // The normal faces the incoming ray, so leaving the object through
+        // a back face swaps the indices on either side.
+        let incidence = -r_in.direction().dot(rec.normal);
+        let (ni_over_nt, cosine) = if rec.front_face {
+            (1.0 / refraction_index, incidence / r_in.direction().length())
         } else {
-            outward_normal = rec.normal;
-            ni_over_nt = 1.0 / self.refraction_index;
-            cosine = -1.0 * (r_in.direction().dot(rec.normal) / r_in.direction().length());
-        }
+            (
+                refraction_index,
+                refraction_index * incidence / r_in.direction().length(),
+            )
+        };
 
-        let wrapped_refracted = self.refract(r_in.direction(), outward_normal, ni_over_nt);
-        if !wrapped_refracted.is_none() {
-            let refracted = wrapped_refracted.unwrap();
-            if rand::random::<f32>() > self.schlick(cosine) {
+        // Always draw the choice so that total internal reflection uses the
+        // same sampler dimensions as refraction.
+        let choice = sampler.next_1d();
+        if let Some(refracted) = self.refract(r_in.direction(), rec.normal, ni_over_nt) {
+            if choice > self.schlick(cosine, refraction_index) {
                 return (
                     attenuation,
                     ray::Ray {
@@ -116,13 +266,51 @@ impl Material for Dielectric {
         }
 
         let reflected = self.reflect(r_in.direction(), rec.normal);
-        return (
+        (
             attenuation,
             ray::Ray {
                 a: rec.p,
                 b: reflected,
             },
             true,
-        );
+        )
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(
+        &self,
+        r_in: ray::Ray,
+        rec: hitable::HitRecord,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (vec3::Vec3, ray::Ray, bool) {
+        self.scatter_with_index(r_in, rec, self.refraction_index, sampler)
+    }
+
+    fn albedo(&self) -> vec3::Vec3 {
+        vec3::Vec3::new(1, 1, 1)
+    }
+
+    fn scatter_spectral(
+        &self,
+        r_in: ray::Ray,
+        rec: hitable::HitRecord,
+        wavelength: f32,
+        sampler: &mut dyn sampler::Sampler,
+    ) -> (vec3::Vec3, ray::Ray, bool) {
+        let refraction_index = self.dispersion.index(self.refraction_index, wavelength);
+        self.scatter_with_index(r_in, rec, refraction_index, sampler)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        !matches!(self.dispersion, Dispersion::None)
+    }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn is_perfectly_specular(&self) -> bool {
+        true
     }
 }
@@ -0,0 +1,158 @@
+use super::aov;
+use super::vec3;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Writes a Portable Float Map with one (`Pf`) or three (`PF`) channels per
+/// pixel. `data` is in top-row-first order; PFM stores rows bottom first.
+pub fn write_pfm<P: AsRef<Path>>(
+    path: P,
+    width: i32,
+    height: i32,
+    channels: usize,
+    data: &[f32],
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let magic = if channels == 1 { "Pf" } else { "PF" };
+    write!(out, "{}\n{} {}\n-1.0\n", magic, width, height)?;
+    let row = width as usize * channels;
+    for y in (0..height as usize).rev() {
+        for v in &data[y * row..(y + 1) * row] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    out.flush()
+}
+
+fn flatten(pixels: &[vec3::Vec3]) -> Vec<f32> {
+    pixels.iter().flat_map(|p| p.e.iter().copied()).collect()
+}
+
+/// Writes every AOV as its own PFM image in `dir`.
+pub fn write_aov_pfms<P: AsRef<Path>>(
+    dir: P,
+    width: i32,
+    height: i32,
+    aovs: &aov::Aovs,
+) -> io::Result<()> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    let ids = |ids: &[u32]| ids.iter().map(|&id| id as f32).collect::<Vec<f32>>();
+    write_pfm(dir.join("albedo.pfm"), width, height, 3, &flatten(&aovs.albedo))?;
+    write_pfm(dir.join("normal.pfm"), width, height, 3, &flatten(&aovs.normal))?;
+    write_pfm(dir.join("position.pfm"), width, height, 3, &flatten(&aovs.position))?;
+    write_pfm(dir.join("depth.pfm"), width, height, 1, &aovs.depth)?;
+    write_pfm(dir.join("object_id.pfm"), width, height, 1, &ids(&aovs.object_id))?;
+    write_pfm(dir.join("primitive_id.pfm"), width, height, 1, &ids(&aovs.primitive_id))?;
+    write_pfm(dir.join("material_id.pfm"), width, height, 1, &ids(&aovs.material_id))?;
+    Ok(())
+}
+
+/// Writes an uncompressed, single-part scanline OpenEXR file with 32-bit float
+/// channels. Channels are `(name, data)` pairs, one value per pixel in
+/// top-row-first order.
+pub fn write_exr<P: AsRef<Path>>(
+    path: P,
+    width: i32,
+    height: i32,
+    channels: &[(String, Vec<f32>)],
+) -> io::Result<()> {
+    // The format requires channels in alphabetical order.
+    let mut channels: Vec<&(String, Vec<f32>)> = channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = Vec::new();
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(kind.as_bytes());
+        header.push(0);
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    };
+
+    let mut chlist = Vec::new();
+    for (name, _) in channels.iter() {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    let mut window = Vec::new();
+    for v in [0, 0, width - 1, height - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    attribute("channels", "chlist", &chlist);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0])?;
+    out.write_all(&header)?;
+
+    // One scanline per block: y coordinate, byte count, then each channel.
+    let line_bytes = 4 * width as u64 * channels.len() as u64;
+    let table_start = 8 + header.len() as u64 + 8 * height as u64;
+    for y in 0..height as u64 {
+        out.write_all(&(table_start + y * (8 + line_bytes)).to_le_bytes())?;
+    }
+    for y in 0..height as usize {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_bytes as i32).to_le_bytes())?;
+        for (_, data) in channels.iter() {
+            for v in &data[y * width as usize..(y + 1) * width as usize] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Writes the beauty image and every AOV as layers of one OpenEXR file.
+pub fn write_aov_exr<P: AsRef<Path>>(
+    path: P,
+    width: i32,
+    height: i32,
+    beauty: &[vec3::Vec3],
+    aovs: &aov::Aovs,
+) -> io::Result<()> {
+    let mut channels = Vec::new();
+    let mut add_vec3 = |layer: &str, names: [&str; 3], pixels: &[vec3::Vec3]| {
+        for (c, name) in names.iter().enumerate() {
+            let full = if layer.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}.{}", layer, name)
+            };
+            channels.push((full, pixels.iter().map(|p| p.e[c]).collect()));
+        }
+    };
+    add_vec3("", ["R", "G", "B"], beauty);
+    add_vec3("albedo", ["R", "G", "B"], &aovs.albedo);
+    add_vec3("normal", ["X", "Y", "Z"], &aovs.normal);
+    add_vec3("position", ["X", "Y", "Z"], &aovs.position);
+    channels.push(("depth.Z".to_string(), aovs.depth.clone()));
+    channels.push((
+        "object.id".to_string(),
+        aovs.object_id.iter().map(|&id| id as f32).collect(),
+    ));
+    channels.push((
+        "primitive.id".to_string(),
+        aovs.primitive_id.iter().map(|&id| id as f32).collect(),
+    ));
+    channels.push((
+        "material.id".to_string(),
+        aovs.material_id.iter().map(|&id| id as f32).collect(),
+    ));
+    write_exr(path, width, height, &channels)
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Image read from a PGM or PPM file, top row first, with every sample
+/// divided by the file's maximum value.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// 1 for greyscale (PGM), 3 for RGB (PPM).
+    pub channels: usize,
+    pub values: Vec<f32>,
+}
+
+/// Reads a binary (P5, P6) or plain (P2, P3) PGM or PPM image.
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+    let data = fs::read(path)?;
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    // Header tokens are separated by whitespace, with '#' comments.
+    let mut pos = 0;
+    let mut header = Vec::new();
+    while header.len() < 4 {
+        while pos < data.len() && data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if pos < data.len() && data[pos] == b'#' {
+            while pos < data.len() && data[pos] != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid("truncated image header"));
+        }
+        header.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+    let parse = |s: &str| s.parse::<usize>().map_err(|_| invalid("bad image header"));
+    let width = parse(&header[1])?;
+    let height = parse(&header[2])?;
+    let maxval = parse(&header[3])?;
+    if width == 0 || height == 0 || maxval == 0 || maxval > 65535 {
+        return Err(invalid("bad image header"));
+    }
+
+    let (channels, binary) = match header[0].as_str() {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        _ => return Err(invalid("not a PGM or PPM image")),
+    };
+    let n = width * height * channels;
+    let values: Vec<f32> = if binary {
+        // Exactly one whitespace byte separates the header from the raster.
+        let raster = &data[(pos + 1).min(data.len())..];
+        if maxval < 256 {
+            if raster.len() < n {
+                return Err(invalid("truncated image raster"));
+            }
+            raster[..n].iter().map(|&b| b as f32).collect()
+        } else {
+            if raster.len() < 2 * n {
+                return Err(invalid("truncated image raster"));
+            }
+            raster[..2 * n]
+                .chunks(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]) as f32)
+                .collect()
+        }
+    } else {
+        let values = String::from_utf8_lossy(&data[pos..])
+            .split_whitespace()
+            .take(n)
+            .map(|s| s.parse::<f32>().map_err(|_| invalid("bad image sample")))
+            .collect::<io::Result<Vec<f32>>>()?;
+        if values.len() < n {
+            return Err(invalid("truncated image raster"));
+        }
+        values
+    };
+
+    Ok(Image {
+        width,
+        height,
+        channels,
+        values: values.into_iter().map(|v| v / maxval as f32).collect(),
+    })
+}
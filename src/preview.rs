@@ -0,0 +1,73 @@
+use super::tonemap;
+use super::vec3;
+use std::io::{self, Write};
+
+/// Shows a downsampled image on a truecolour terminal. Every character cell is
+/// an upper half block with the top pixel as foreground and the bottom one as
+/// background colour, so terminal pixels come out roughly square.
+pub struct Preview {
+    columns: usize,
+    /// Lines printed by the previous `draw`, to move back over.
+    lines: usize,
+}
+
+impl Preview {
+    pub fn new(columns: usize) -> Self {
+        Self {
+            columns: columns.max(1),
+            lines: 0,
+        }
+    }
+
+    /// Draws `pixels`, top row first, over the previous drawing. Each preview
+    /// pixel is the average of the block of image pixels it covers. An empty
+    /// image draws nothing.
+    pub fn draw(
+        &mut self,
+        out: &mut dyn Write,
+        pixels: &[vec3::Vec3],
+        width: i32,
+        height: i32,
+        pipeline: &tonemap::Pipeline,
+    ) -> io::Result<()> {
+        if width <= 0 || height <= 0 {
+            return Ok(());
+        }
+        let (width, height) = (width as usize, height as usize);
+        let columns = self.columns.min(width);
+        let rows = ((height * columns + width / 2) / width).max(1);
+        let block = |x: usize, y: usize| {
+            let (x0, x1) = (x * width / columns, (x + 1) * width / columns);
+            let (y0, y1) = (y * height / rows, (y + 1) * height / rows);
+            let mut sum = vec3::Vec3::new(0, 0, 0);
+            for row in y0..y1 {
+                for col in x0..x1 {
+                    sum += pixels[row * width + col];
+                }
+            }
+            let col = sum / ((x1 - x0) * (y1 - y0)) as f32;
+            let [r, g, b] = pipeline.quantize(col, x as i32, y as i32);
+            format!("{};{};{}", r, g, b)
+        };
+
+        let mut text = String::new();
+        if self.lines > 0 {
+            text.push_str(&format!("\r\x1b[{}A\x1b[J", self.lines));
+        }
+        for y in (0..rows).step_by(2) {
+            for x in 0..columns {
+                text.push_str(&format!("\x1b[38;2;{}m", block(x, y)));
+                if y + 1 < rows {
+                    text.push_str(&format!("\x1b[48;2;{}m", block(x, y + 1)));
+                } else {
+                    text.push_str("\x1b[49m");
+                }
+                text.push('\u{2580}');
+            }
+            text.push_str("\x1b[0m\n");
+        }
+        self.lines = rows.div_ceil(2);
+        out.write_all(text.as_bytes())?;
+        out.flush()
+    }
+}
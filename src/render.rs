@@ -0,0 +1,592 @@
+use super::aov;
+use super::camera;
+use super::checkpoint;
+use super::differential;
+use super::film;
+use super::hitable;
+use super::integrator;
+use super::ray;
+use super::sampler::Sampler;
+use super::settings;
+use super::spectrum;
+use super::stats;
+use super::tiles;
+use super::vec3;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Bounds on path length. Each scattering event counts towards `max_depth`
+/// and towards the limit for its kind; transmission through a surface is
+/// counted separately from specular reflection.
+#[derive(Copy, Clone)]
+pub struct PathLimits {
+    pub max_depth: u32,
+    pub max_diffuse: u32,
+    pub max_specular: u32,
+    pub max_transmission: u32,
+    /// Paths longer than this are terminated at random with a probability
+    /// based on their throughput, and the survivors reweighted.
+    pub roulette_depth: u32,
+}
+
+impl Default for PathLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 50,
+            max_diffuse: 50,
+            max_specular: 50,
+            max_transmission: 50,
+            roulette_depth: 5,
+        }
+    }
+}
+
+#[derive(Default)]
+struct BounceCounts {
+    total: u32,
+    diffuse: u32,
+    specular: u32,
+    transmission: u32,
+}
+
+impl BounceCounts {
+    /// Counts a scattering event and returns whether the path is still within
+    /// its limits.
+    fn record(
+        &mut self,
+        limits: &PathLimits,
+        rec: &hitable::HitRecord,
+        r_in: ray::Ray,
+        scattered: ray::Ray,
+    ) -> bool {
+        self.total += 1;
+        let incoming = r_in.direction().dot(rec.geometric_normal);
+        let outgoing = scattered.direction().dot(rec.geometric_normal);
+        if incoming * outgoing > 0.0 {
+            self.transmission += 1;
+            self.transmission <= limits.max_transmission
+        } else if rec.material.is_specular() {
+            self.specular += 1;
+            self.specular <= limits.max_specular
+        } else {
+            self.diffuse += 1;
+            self.diffuse <= limits.max_diffuse
+        }
+    }
+
+    /// Russian roulette. Returns the survival probability to divide the
+    /// throughput by, or `None` if the path should stop.
+    fn roulette(
+        &self,
+        limits: &PathLimits,
+        max_throughput: f32,
+        sampler: &mut dyn Sampler,
+    ) -> Option<f32> {
+        if self.total < limits.roulette_depth {
+            return Some(1.0);
+        }
+        let survival = max_throughput.min(0.95);
+        if sampler.next_1d() < survival {
+            Some(survival)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn color(
+    mut r: ray::Ray,
+    mut differential: Option<differential::Differential>,
+    world: &dyn hitable::Hitable,
+    limits: &PathLimits,
+    sampler: &mut dyn Sampler,
+) -> vec3::Vec3 {
+    let mut throughput = vec3::Vec3::new(1, 1, 1);
+    let mut bounces = BounceCounts::default();
+    while let Some(mut rec) = world.hit(r, 0.001, f32::INFINITY) {
+        if bounces.total >= limits.max_depth {
+            return vec3::Vec3::new(0, 0, 0);
+        }
+        let offsets = differential.map(|d| d.project(&mut rec));
+        let (attenuation, scattered, scatter_bool) = rec.material.scatter(r, rec, sampler);
+        if !scatter_bool || !bounces.record(limits, &rec, r, scattered) {
+            return vec3::Vec3::new(0, 0, 0);
+        }
+        differential = follow(differential, offsets, r, &rec, scattered);
+        throughput *= attenuation;
+        let max_throughput = throughput.r().max(throughput.g()).max(throughput.b());
+        match bounces.roulette(limits, max_throughput, sampler) {
+            Some(survival) => throughput /= survival,
+            None => return vec3::Vec3::new(0, 0, 0),
+        }
+        r = scattered;
+    }
+    throughput * background(r)
+}
+
+/// Carries a ray differential across a bounce. Only perfectly specular
+/// bounces keep it: after a diffuse or blurred one, neighbouring pixels' paths
+/// no longer stay close.
+fn follow(
+    differential: Option<differential::Differential>,
+    offsets: Option<(vec3::Vec3, vec3::Vec3)>,
+    r: ray::Ray,
+    rec: &hitable::HitRecord,
+    scattered: ray::Ray,
+) -> Option<differential::Differential> {
+    match (differential, offsets) {
+        (Some(d), Some(offsets)) if rec.material.is_perfectly_specular() => {
+            d.scatter(r, rec, offsets, scattered)
+        }
+        _ => None,
+    }
+}
+
+fn background(r: ray::Ray) -> vec3::Vec3 {
+    let unit_direction = vec3::unit_vector(r.direction());
+    let t = 0.5 * (unit_direction.y() + 1.0);
+    (1.0 - t) * vec3::Vec3::new(1, 1, 1)
+        + t * vec3::Vec3::new(0.5, 0.7, 1.0)
+}
+
+/// Spectral counterpart of `color`, tracing several wavelengths along one
+/// path. RGB albedos and the sky are upsampled to spectra. When the path
+/// meets a dispersive material, only the first (hero) wavelength continues.
+pub fn color_spectral(
+    mut r: ray::Ray,
+    mut differential: Option<differential::Differential>,
+    world: &dyn hitable::Hitable,
+    wavelengths: &[f32; spectrum::WAVELENGTHS],
+    limits: &PathLimits,
+    sampler: &mut dyn Sampler,
+) -> [f32; spectrum::WAVELENGTHS] {
+    let mut throughput = [1.0; spectrum::WAVELENGTHS];
+    let mut bounces = BounceCounts::default();
+    let mut single_wavelength = false;
+    while let Some(mut rec) = world.hit(r, 0.001, f32::INFINITY) {
+        if bounces.total >= limits.max_depth {
+            return [0.0; spectrum::WAVELENGTHS];
+        }
+        let offsets = differential.map(|d| d.project(&mut rec));
+        if !single_wavelength && rec.material.is_dispersive() {
+            single_wavelength = true;
+            throughput[0] *= spectrum::WAVELENGTHS as f32;
+            for t in throughput.iter_mut().skip(1) {
+                *t = 0.0;
+            }
+        }
+        let (attenuation, scattered, scatter_bool) =
+            rec.material.scatter_spectral(r, rec, wavelengths[0], sampler);
+        if !scatter_bool || !bounces.record(limits, &rec, r, scattered) {
+            return [0.0; spectrum::WAVELENGTHS];
+        }
+        differential = follow(differential, offsets, r, &rec, scattered);
+        for k in 0..spectrum::WAVELENGTHS {
+            throughput[k] *= spectrum::rgb_to_spectrum(attenuation, wavelengths[k]);
+        }
+        let max_throughput = throughput.iter().copied().fold(0.0, f32::max);
+        match bounces.roulette(limits, max_throughput, sampler) {
+            Some(survival) => throughput.iter_mut().for_each(|t| *t /= survival),
+            None => return [0.0; spectrum::WAVELENGTHS],
+        }
+        r = scattered;
+    }
+    let sky = background(r);
+    let mut radiance = [0.0; spectrum::WAVELENGTHS];
+    for k in 0..spectrum::WAVELENGTHS {
+        radiance[k] = throughput[k] * spectrum::rgb_to_spectrum(sky, wavelengths[k]);
+    }
+    radiance
+}
+
+pub fn luminance(c: vec3::Vec3) -> f32 {
+    0.2126 * c.r() + 0.7152 * c.g() + 0.0722 * c.b()
+}
+
+/// Result of a render. Pixels are stored in output order, top row first.
+pub struct Frame {
+    pub width: i32,
+    pub height: i32,
+    /// Average radiance of each pixel.
+    pub pixels: Vec<vec3::Vec3>,
+    /// Number of samples taken in each pixel.
+    pub sample_counts: Vec<u32>,
+    pub aovs: Option<aov::Aovs>,
+}
+
+impl Frame {
+    /// Cuts `region` out of the frame or, with `keep_size`, blacks out the
+    /// pixels around it and clears their sample counts and AOVs instead.
+    fn crop(mut self, region: tiles::Region, keep_size: bool) -> Frame {
+        if keep_size {
+            region.mask(&mut self.pixels, self.width, vec3::Vec3::new(0, 0, 0));
+            region.mask(&mut self.sample_counts, self.width, 0);
+            if let Some(aovs) = self.aovs.as_mut() {
+                aovs.mask(region, self.width);
+            }
+            return self;
+        }
+        let width = self.width;
+        Frame {
+            width: region.width(),
+            height: region.height(),
+            pixels: region.crop(&self.pixels, width),
+            sample_counts: region.crop(&self.sample_counts, width),
+            aovs: self.aovs.map(|aovs| aovs.crop(region, width)),
+        }
+    }
+}
+
+/// Running luminance statistics of a pixel's samples, used to estimate its
+/// noise.
+#[derive(Copy, Clone)]
+pub struct PixelStats {
+    pub luminance_sum: f64,
+    pub luminance_squared_sum: f64,
+    pub count: u32,
+}
+
+impl PixelStats {
+    fn new() -> Self {
+        Self {
+            luminance_sum: 0.0,
+            luminance_squared_sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, col: vec3::Vec3) {
+        let l = luminance(col) as f64;
+        self.luminance_sum += l;
+        self.luminance_squared_sum += l * l;
+        self.count += 1;
+    }
+
+    /// Standard error of the mean luminance relative to the mean itself. The
+    /// small floor on the mean stops black pixels from never converging.
+    fn error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+        let n = self.count as f64;
+        let mean = self.luminance_sum / n;
+        let variance = ((self.luminance_squared_sum - mean * self.luminance_sum) / (n - 1.0)).max(0.0);
+        ((variance / n).sqrt() / (mean + 0.01)) as f32
+    }
+}
+
+/// Average relative noise of the given pixels.
+fn mean_error(stats: &[PixelStats], pixels: &[usize]) -> f32 {
+    let sum: f64 = pixels.iter().map(|&index| stats[index].error() as f64).sum();
+    (sum / pixels.len().max(1) as f64) as f32
+}
+
+/// Stops a render from another thread. The render finishes the pixel it is
+/// working on and returns an image with the samples taken so far.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Reported to the progress callback after every pass.
+pub struct Progress<'a> {
+    /// Passes finished so far in this run.
+    pub passes: u32,
+    /// Samples taken so far, including those resumed from a checkpoint.
+    pub samples: u64,
+    /// Samples the render takes if it runs to the end.
+    pub total_samples: u64,
+    /// Rays traced so far in this run.
+    pub rays: u64,
+    pub elapsed: Duration,
+    /// Estimated time until the render finishes, from the rate so far.
+    pub eta: Option<Duration>,
+    /// Average relative noise of the pixels, as used by `target_noise`.
+    pub noise: f32,
+    /// The image so far.
+    pub film: &'a film::Film,
+}
+
+/// Lets the caller of `render` watch and stop it while it runs.
+#[derive(Default)]
+pub struct RenderControl<'a> {
+    pub cancel: Option<CancelToken>,
+    pub progress: Option<&'a mut dyn FnMut(&Progress)>,
+}
+
+/// Counts the rays traced into the world it wraps.
+struct RayCounter<'a> {
+    world: &'a dyn hitable::Hitable,
+    rays: Cell<u64>,
+}
+
+impl hitable::Hitable for RayCounter<'_> {
+    fn hit(&self, r: ray::Ray, t_min: f32, t_max: f32) -> Option<hitable::HitRecord<'_>> {
+        self.rays.set(self.rays.get() + 1);
+        self.world.hit(r, t_min, t_max)
+    }
+
+    fn occluded(&self, r: ray::Ray, t_min: f32, t_max: f32) -> bool {
+        self.rays.set(self.rays.get() + 1);
+        self.world.occluded(r, t_min, t_max)
+    }
+}
+
+/// Adaptive sampling never gives a single pixel more than this many times the
+/// average sample count.
+const ADAPTIVE_MAX_FACTOR: u32 = 8;
+
+pub fn render(
+    cam: &dyn camera::Camera,
+    world: &dyn hitable::Hitable,
+    sampler: &mut dyn Sampler,
+    settings: &settings::RenderSettings,
+    resume: Option<checkpoint::Checkpoint>,
+    control: RenderControl,
+) -> Frame {
+    let (nx, ny, ns) = (settings.nx, settings.ny, settings.ns);
+    let mut film = film::Film::new(
+        nx,
+        ny,
+        settings.filter,
+        settings.filter_radius.unwrap_or(settings.filter.default_radius()),
+    );
+    let (mut stats, mut seeds) = match resume {
+        Some(checkpoint) => {
+            film.add_accumulated(&checkpoint.sums, &checkpoint.weights);
+            (checkpoint.stats, checkpoint.seeds)
+        }
+        None => (vec![PixelStats::new(); (nx * ny) as usize], Vec::new()),
+    };
+    // New samples continue from each pixel's sample count, so they never repeat
+    // samples already taken with this seed.
+    if !seeds.contains(&settings.seed) {
+        seeds.push(settings.seed);
+    }
+    let counter = RayCounter {
+        world,
+        rays: Cell::new(0),
+    };
+    let world: &dyn hitable::Hitable = &counter;
+    let integrator = integrator::from_settings(settings);
+    let mut aovs = if settings.wants_aovs() {
+        Some(aov::AovBuffers::new(nx, ny))
+    } else {
+        None
+    };
+
+    // A crop window is rendered with a margin as wide as the filter, so that
+    // its edge pixels get the same samples as in a render of the whole frame.
+    let region = match settings.crop {
+        Some(crop) => crop.region(nx, ny),
+        None => tiles::Region::full(nx, ny),
+    };
+    let margin = (film.radius() - 0.5).ceil() as i32;
+    let order = tiles::pixel_order(region.expand(margin, nx, ny), settings.tile_order, nx);
+
+    let mut take_samples = |film: &mut film::Film, index: usize, stats: &mut PixelStats, n: u32| {
+        let i = index as i32 % nx;
+        let j = ny - 1 - index as i32 / nx;
+        for _ in 0..n {
+            sampler.start_sample(i, j, stats.count as i32);
+            let (du, dv) = sampler.next_2d();
+            let u = (i as f32 + du) / nx as f32;
+            let v = (j as f32 + dv) / ny as f32;
+            if !cam.sees(u, v) {
+                let black = vec3::Vec3::new(0, 0, 0);
+                if let Some(aovs) = aovs.as_mut() {
+                    aovs.add_sample(index, None, black);
+                }
+                stats.add(black);
+                film.add_sample(i as f32 + du, j as f32 + dv, black);
+                continue;
+            }
+            let (r, differential) =
+                cam.get_ray_differential(u, v, 1.0 / nx as f32, 1.0 / ny as f32, sampler);
+            if let Some(aovs) = aovs.as_mut() {
+                aovs.add_sample(index, world.hit(r, 0.001, f32::INFINITY), background(r));
+            }
+            let rays_before = counter.rays.get();
+            let col = integrator.li(r, Some(differential), world, sampler);
+            stats::count_path(counter.rays.get() - rays_before);
+            stats.add(col);
+            film.add_sample(i as f32 + du, j as f32 + dv, col);
+        }
+    };
+
+    let mut last_checkpoint = Instant::now();
+    let mut save_checkpoint = |film: &film::Film, stats: &[PixelStats], force: bool| {
+        let path = match &settings.checkpoint {
+            Some(path) => path,
+            None => return,
+        };
+        if !force && last_checkpoint.elapsed().as_secs_f32() < settings.checkpoint_interval {
+            return;
+        }
+        let (sums, weights) = film.accumulated();
+        let checkpoint = checkpoint::Checkpoint {
+            width: nx,
+            height: ny,
+            setup: checkpoint::Setup::new(settings),
+            seeds: seeds.clone(),
+            sums: sums.to_vec(),
+            weights: weights.to_vec(),
+            stats: stats.to_vec(),
+        };
+        if let Err(e) = checkpoint.write(path) {
+            eprintln!("cannot write checkpoint {}: {}", path, e);
+        }
+        last_checkpoint = Instant::now();
+    };
+
+    // The time limit and cancellation are checked between pixels, but only
+    // once a pixel has samples, so every pixel gets at least one.
+    let start = Instant::now();
+    let RenderControl { cancel, mut progress } = control;
+    let interrupted = || {
+        settings
+            .time_limit
+            .is_some_and(|limit| start.elapsed().as_secs_f32() >= limit)
+            || cancel.as_ref().is_some_and(|cancel| cancel.is_cancelled())
+    };
+    let total_samples = ns.max(0) as u64 * order.len() as u64;
+    let count_samples = |stats: &[PixelStats]| -> u64 {
+        order.iter().map(|&index| stats[index].count as u64).sum()
+    };
+    let resumed_samples = count_samples(&stats);
+    let mut passes = 0;
+    // Reports a finished pass and saves a checkpoint if one is due. Returns
+    // whether the frame has reached the target noise.
+    let mut end_pass = |film: &film::Film, stats: &[PixelStats]| {
+        passes += 1;
+        let noise = mean_error(stats, &order);
+        if let Some(progress) = progress.as_mut() {
+            let samples = count_samples(stats);
+            let elapsed = start.elapsed();
+            let mut eta = (samples > resumed_samples).then(|| {
+                let remaining = total_samples.saturating_sub(samples);
+                elapsed.mul_f64(remaining as f64 / (samples - resumed_samples) as f64)
+            });
+            if let Some(limit) = settings.time_limit {
+                // No ETA for a limit too long to hold in a `Duration`.
+                eta = Duration::try_from_secs_f32(limit).ok().map(|limit| {
+                    let left = limit.saturating_sub(elapsed);
+                    eta.map_or(left, |eta| eta.min(left))
+                });
+            }
+            progress(&Progress {
+                passes,
+                samples,
+                total_samples,
+                rays: counter.rays.get(),
+                elapsed,
+                eta,
+                noise,
+                film,
+            });
+        }
+        save_checkpoint(film, stats, false);
+        settings.target_noise.is_some_and(|target| noise <= target)
+    };
+
+    match settings.noise_threshold {
+        None => {
+            // Progressive passes, so that stopping or taking a checkpoint
+            // between them leaves the whole frame at about the same sample
+            // count.
+            let pass = settings.pass_samples.max(1) as u32;
+            'passes: loop {
+                let mut taken = false;
+                for &index in &order {
+                    let pixel = &mut stats[index];
+                    if pixel.count > 0 && interrupted() {
+                        break 'passes;
+                    }
+                    let n = pass.min((ns.max(0) as u32).saturating_sub(pixel.count));
+                    if n > 0 {
+                        take_samples(&mut film, index, pixel, n);
+                        taken = true;
+                    }
+                }
+                if !taken || end_pass(&film, &stats) {
+                    break;
+                }
+            }
+        }
+        Some(threshold) => {
+            // Every pixel gets a few samples to estimate its noise; the rest of
+            // the budget of `ns` samples per pixel goes, in passes, to the
+            // pixels still above the threshold, noisiest first.
+            let batch = (settings.min_samples.min(ns).max(2)) as u32;
+            let max_samples = ns as u32 * ADAPTIVE_MAX_FACTOR;
+            let mut budget = total_samples.saturating_sub(resumed_samples);
+            let mut stopped = false;
+            for &index in &order {
+                let pixel = &mut stats[index];
+                if pixel.count > 0 && interrupted() {
+                    stopped = true;
+                    break;
+                }
+                let n = batch.saturating_sub(pixel.count);
+                take_samples(&mut film, index, pixel, n);
+                budget = budget.saturating_sub(n as u64);
+            }
+            stopped = stopped || end_pass(&film, &stats);
+            while budget > 0 && !stopped {
+                let mut active: Vec<(f32, usize)> = order
+                    .iter()
+                    .map(|&index| (stats[index].error(), index))
+                    .filter(|&(error, index)| {
+                        error > threshold && stats[index].count < max_samples
+                    })
+                    .collect();
+                if active.is_empty() {
+                    break;
+                }
+                active.sort_by(|a, b| b.0.total_cmp(&a.0));
+                for (_, index) in active {
+                    if interrupted() {
+                        stopped = true;
+                        break;
+                    }
+                    let pixel = &mut stats[index];
+                    let n = (batch.min(max_samples - pixel.count) as u64).min(budget) as u32;
+                    take_samples(&mut film, index, pixel, n);
+                    budget -= n as u64;
+                    if budget == 0 {
+                        break;
+                    }
+                }
+                stopped = stopped || end_pass(&film, &stats);
+            }
+        }
+    }
+    save_checkpoint(&film, &stats, true);
+
+    let frame = Frame {
+        width: nx,
+        height: ny,
+        pixels: film.pixels(),
+        sample_counts: stats.iter().map(|pixel| pixel.count).collect(),
+        aovs: aovs.map(|aovs| aovs.finish()),
+    };
+    if settings.crop.is_some() {
+        frame.crop(region, settings.full_frame)
+    } else {
+        frame
+    }
+}
@@ -0,0 +1,500 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Source of the random numbers used while tracing a path. Callers start every
+/// camera sample with `start_sample`; everything drawn afterwards depends only
+/// on the sampler's seed and the pixel and sample index, so renders are
+/// reproducible regardless of the order in which pixels are visited.
+///
+/// Each call draws the next sample dimension(s). Callers consume dimensions in
+/// a fixed order (pixel position, lens, then a fixed number per bounce) so that
+/// low-discrepancy samplers line up the same dimension across a pixel's samples.
+pub trait Sampler {
+    fn start_sample(&mut self, x: i32, y: i32, index: i32);
+
+    /// Returns a number in [0, 1).
+    fn next_1d(&mut self) -> f32;
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        (self.next_1d(), self.next_1d())
+    }
+}
+
+/// Independent uniform random numbers, reseeded for every camera sample.
+pub struct RandomSampler {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn start_sample(&mut self, x: i32, y: i32, index: i32) {
+        let mut h = mix(self.seed);
+        h = mix(h ^ x as u32 as u64);
+        h = mix(h ^ y as u32 as u64);
+        h = mix(h ^ index as u32 as u64);
+        self.rng = StdRng::seed_from_u64(h);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        self.rng.gen::<f32>()
+    }
+}
+
+/// Stratifies every dimension into `samples_per_pixel` strata, visiting them in
+/// a different random order per pixel and dimension. Two-dimensional draws use
+/// a jittered grid when the sample count is a perfect square.
+pub struct StratifiedSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: i32) -> Self {
+        Self {
+            seed,
+            samples_per_pixel: samples_per_pixel.max(1) as u32,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Stratum of the current sample in the next dimension, plus random bits
+    /// for jittering within it. Samples past `samples_per_pixel` start a fresh
+    /// permutation.
+    fn stratum(&mut self, strata: u32) -> (u32, u64) {
+        let pass = self.index / strata;
+        let h = mix(self.pixel_seed ^ mix(((pass as u64) << 32) | self.dimension as u64));
+        let stratum = permute(self.index % strata, strata, h as u32);
+        self.dimension += 1;
+        (stratum, mix(h ^ self.index as u64))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.pixel_seed = pixel_hash(self.seed, x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let n = self.samples_per_pixel;
+        let (stratum, bits) = self.stratum(n);
+        ((stratum as f32 + to_unit_float(bits as u32)) / n as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let n = self.samples_per_pixel;
+        let side = (n as f32).sqrt() as u32;
+        if side * side != n {
+            return (self.next_1d(), self.next_1d());
+        }
+        let (stratum, bits) = self.stratum(n);
+        self.dimension += 1;
+        let jx = to_unit_float(bits as u32);
+        let jy = to_unit_float((bits >> 32) as u32);
+        (
+            (((stratum % side) as f32 + jx) / side as f32).min(ONE_MINUS_EPSILON),
+            (((stratum / side) as f32 + jy) / side as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89,
+    97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+/// Halton sequence, one prime base per dimension, decorrelated between pixels
+/// by a random toroidal shift. Dimensions past the prime table fall back to
+/// hashed random numbers.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.pixel_seed = pixel_hash(self.seed, x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let h = mix(self.pixel_seed ^ self.dimension as u64);
+        let value = match PRIMES.get(self.dimension as usize) {
+            Some(&base) => {
+                let shifted = radical_inverse(base, self.index) + to_unit_float(h as u32);
+                shifted - shifted.floor()
+            }
+            None => to_unit_float(mix(h ^ self.index as u64) as u32),
+        };
+        self.dimension += 1;
+        value.min(ONE_MINUS_EPSILON)
+    }
+}
+
+/// Owen-scrambled Sobol points using hash-based nested uniform scrambling
+/// (Burley 2020). Every pair of dimensions draws the first two Sobol dimensions
+/// with its own index shuffle and scramble, so any number of dimensions can be
+/// drawn while each pair stays well stratified.
+pub struct SobolSampler {
+    seed: u64,
+    pixel_seed: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn draw(&mut self) -> (f32, f32) {
+        let seed = hash_combine(self.pixel_seed, self.dimension);
+        let index = nested_uniform_scramble(self.index, seed);
+        let x = nested_uniform_scramble(sobol_dimension_0(index), hash_combine(seed, 0));
+        let y = nested_uniform_scramble(sobol_dimension_1(index), hash_combine(seed, 1));
+        (to_unit_float(x), to_unit_float(y))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.pixel_seed = pixel_hash(self.seed, x, y) as u32;
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let (x, _) = self.draw();
+        self.dimension += 1;
+        x
+    }
+
+    fn next_2d(&mut self) -> (f32, f32) {
+        let xy = self.draw();
+        self.dimension += 2;
+        xy
+    }
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// Passes draws through from another sampler and remembers the first `N`, so
+/// that they can be replayed with `Replay`. Later draws are not recorded.
+pub struct Recorder<'a, const N: usize> {
+    inner: &'a mut dyn Sampler,
+    values: [f32; N],
+    len: usize,
+}
+
+impl<'a, const N: usize> Recorder<'a, N> {
+    pub fn new(inner: &'a mut dyn Sampler) -> Self {
+        Self {
+            inner,
+            values: [0.0; N],
+            len: 0,
+        }
+    }
+
+    /// Everything recorded so far, in order.
+    pub fn values(&self) -> &[f32] {
+        &self.values[..self.len]
+    }
+
+    fn record(&mut self, value: f32) {
+        if self.len < N {
+            self.values[self.len] = value;
+            self.len += 1;
+        }
+    }
+}
+
+impl<const N: usize> Sampler for Recorder<'_, N> {
+    fn start_sample(&mut self, x: i32, y: i32, index: i32) {
+        self.inner.start_sample(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let value = self.inner.next_1d();
+        self.record(value);
+        value
+    }
+
+    // Forwarded as a pair, since samplers may stratify both dimensions
+    // together.
+    fn next_2d(&mut self) -> (f32, f32) {
+        let (a, b) = self.inner.next_2d();
+        self.record(a);
+        self.record(b);
+        (a, b)
+    }
+}
+
+/// Draws the values captured by a `Recorder` again, then 0.5 once they run
+/// out.
+pub struct Replay<'a> {
+    values: &'a [f32],
+    next: usize,
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(values: &'a [f32]) -> Self {
+        Self { values, next: 0 }
+    }
+}
+
+impl Sampler for Replay<'_> {
+    fn start_sample(&mut self, _x: i32, _y: i32, _index: i32) {
+        self.next = 0;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let value = self.values.get(self.next).copied().unwrap_or(0.5);
+        self.next += 1;
+        value
+    }
+}
+
+/// Maps 32 random bits to [0, 1), keeping the top 24 so the result is exact.
+fn to_unit_float(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+fn pixel_hash(seed: u64, x: i32, y: i32) -> u64 {
+    mix(mix(mix(seed) ^ x as u32 as u64) ^ y as u32 as u64)
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f64 * inv;
+        index /= base;
+        inv *= inv_base;
+    }
+    result as f32
+}
+
+/// Kensler's hash-based permutation of `0..n`, without storing the permutation.
+fn permute(mut i: u32, n: u32, p: u32) -> u32 {
+    if n <= 1 {
+        return 0;
+    }
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % n
+}
+
+fn sobol_dimension_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+fn sobol_dimension_1(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut v = 1u32 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// Owen scrambling in base 2: flips each bit depending on all higher bits.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn hash_combine(seed: u32, v: u32) -> u32 {
+    seed ^ (v
+        .wrapping_add(seed << 6)
+        .wrapping_add(seed >> 2)
+        .wrapping_add(0x9e37_79b9))
+}
+
+/// SplitMix64 finaliser, used to combine seed and sample coordinates.
+fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{camera, hitable, material, render, settings, sphere, tiles, vec3};
+
+    fn samplers(seed: u64) -> Vec<Box<dyn Sampler>> {
+        vec![
+            Box::new(RandomSampler::new(seed)),
+            Box::new(StratifiedSampler::new(seed, 16)),
+            Box::new(HaltonSampler::new(seed)),
+            Box::new(SobolSampler::new(seed)),
+        ]
+    }
+
+    /// The first values drawn for a few samples of a few pixels.
+    fn draws(sampler: &mut dyn Sampler) -> Vec<f32> {
+        let mut values = Vec::new();
+        for (x, y) in [(0, 0), (3, 1), (17, 42)].iter() {
+            for index in 0..4 {
+                sampler.start_sample(*x, *y, index);
+                for _ in 0..3 {
+                    values.push(sampler.next_1d());
+                    let (a, b) = sampler.next_2d();
+                    values.extend_from_slice(&[a, b]);
+                }
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn same_seed_draws_the_same_values() {
+        for (a, b) in samplers(7).iter_mut().zip(samplers(7).iter_mut()) {
+            assert_eq!(draws(a.as_mut()), draws(b.as_mut()));
+        }
+    }
+
+    #[test]
+    fn different_seeds_draw_different_values() {
+        for (a, b) in samplers(7).iter_mut().zip(samplers(8).iter_mut()) {
+            assert_ne!(draws(a.as_mut()), draws(b.as_mut()));
+        }
+    }
+
+    #[test]
+    fn values_lie_in_the_unit_interval() {
+        for sampler in samplers(3).iter_mut() {
+            assert!(draws(sampler.as_mut()).iter().all(|&v| (0.0..1.0).contains(&v)));
+        }
+    }
+
+    fn render_tiny(order: tiles::TileOrder, sampler: &mut dyn Sampler) -> Vec<[f32; 3]> {
+        let world = hitable::HitableList {
+            list: vec![
+                Box::new(sphere::Sphere {
+                    center: vec3::Vec3::new(0, -100.5, -1),
+                    radius: 100.0,
+                    material: Box::new(material::Lambertian {
+                        albedo: vec3::Vec3::new(0.5, 0.5, 0.5),
+                    }),
+                }),
+                Box::new(sphere::Sphere {
+                    center: vec3::Vec3::new(0, 0, -1),
+                    radius: 0.5,
+                    material: Box::new(material::Metal {
+                        albedo: vec3::Vec3::new(0.8, 0.6, 0.2),
+                        fuzz: 0.3,
+                    }),
+                }),
+            ],
+        };
+        let settings = settings::RenderSettings {
+            nx: 12,
+            ny: 8,
+            ns: 4,
+            tile_order: order,
+            ..Default::default()
+        };
+        let cam = camera::NoBlurCamera::new(
+            vec3::Vec3::new(0, 0.5, 1),
+            vec3::Vec3::new(0, 0, -1),
+            vec3::Vec3::new(0, 1, 0),
+            60.0,
+            1.5,
+        );
+        let frame = render::render(
+            &cam,
+            &world,
+            sampler,
+            &settings,
+            None,
+            render::RenderControl::default(),
+        );
+        frame.pixels.iter().map(|p| p.e).collect()
+    }
+
+    #[test]
+    fn renders_are_bit_identical() {
+        let (mut first, mut second, mut third) = (samplers(5), samplers(5), samplers(5));
+        for ((a, b), c) in first.iter_mut().zip(second.iter_mut()).zip(third.iter_mut()) {
+            let image = render_tiny(tiles::TileOrder::Scanline, a.as_mut());
+            assert_eq!(image, render_tiny(tiles::TileOrder::Scanline, b.as_mut()));
+            assert_eq!(image, render_tiny(tiles::TileOrder::Hilbert, c.as_mut()));
+        }
+    }
+}
@@ -0,0 +1,616 @@
+use super::camera::{PhysicalCamera, StereoLayout};
+use super::film::Filter;
+use super::render::PathLimits;
+use super::texture::TextureFilter;
+use super::tiles::{Region, TileOrder};
+use super::tonemap::ToneMap;
+use std::convert::TryFrom;
+use std::env;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum CameraKind {
+    NoBlur,
+    DefocusBlur,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+    Stereo,
+    Ods,
+    Physical,
+}
+
+impl CameraKind {
+    fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "noblur" => Ok(CameraKind::NoBlur),
+            "defocus" => Ok(CameraKind::DefocusBlur),
+            "ortho" => Ok(CameraKind::Orthographic),
+            "fisheye" => Ok(CameraKind::Fisheye),
+            "equirect" => Ok(CameraKind::Equirectangular),
+            "stereo" => Ok(CameraKind::Stereo),
+            "ods" => Ok(CameraKind::Ods),
+            "physical" => Ok(CameraKind::Physical),
+            _ => Err(format!("unknown camera '{}'", s)),
+        }
+    }
+}
+
+fn parse_stereo_layout(s: &str) -> Result<StereoLayout, String> {
+    match s {
+        "sbs" => Ok(StereoLayout::SideBySide),
+        "tb" => Ok(StereoLayout::TopBottom),
+        _ => Err(format!("unknown stereo layout '{}'", s)),
+    }
+}
+
+fn parse_filter(s: &str) -> Result<Filter, String> {
+    match s {
+        "box" => Ok(Filter::Box),
+        "tent" => Ok(Filter::Tent),
+        "gaussian" => Ok(Filter::Gaussian),
+        "mitchell" => Ok(Filter::MitchellNetravali),
+        "lanczos" => Ok(Filter::Lanczos),
+        _ => Err(format!("unknown filter '{}'", s)),
+    }
+}
+
+fn parse_tone_map(s: &str) -> Result<ToneMap, String> {
+    match s {
+        "clamp" => Ok(ToneMap::Clamp),
+        "reinhard" => Ok(ToneMap::Reinhard),
+        "aces" => Ok(ToneMap::Aces),
+        "agx" => Ok(ToneMap::Agx),
+        _ => Err(format!("unknown tone map '{}'", s)),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Scene {
+    /// The random spheres from the end of the book.
+    Random,
+    /// Strongly dispersive glass and diamond spheres, for spectral renders.
+    Dispersion,
+    /// A finely textured floor running to the horizon, with textured and
+    /// mirrored spheres, to compare texture filters.
+    Textured,
+    /// Shapes built with constructive solid geometry, such as a hollow glass
+    /// sphere and a lens.
+    Csg,
+}
+
+impl Scene {
+    fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "random" => Ok(Scene::Random),
+            "dispersion" => Ok(Scene::Dispersion),
+            "textured" => Ok(Scene::Textured),
+            "csg" => Ok(Scene::Csg),
+            _ => Err(format!("unknown scene '{}'", s)),
+        }
+    }
+}
+
+/// What the renderer computes for each camera ray.
+#[derive(Copy, Clone, PartialEq)]
+pub enum IntegratorKind {
+    /// Path-traced radiance.
+    Path,
+    AmbientOcclusion,
+    Normals,
+    Uv,
+    Depth,
+}
+
+impl IntegratorKind {
+    fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "path" => Ok(IntegratorKind::Path),
+            "ao" => Ok(IntegratorKind::AmbientOcclusion),
+            "normals" => Ok(IntegratorKind::Normals),
+            "uv" => Ok(IntegratorKind::Uv),
+            "depth" => Ok(IntegratorKind::Depth),
+            _ => Err(format!("unknown integrator '{}'", s)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum SamplerKind {
+    Random,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub const ALL: [(&'static str, SamplerKind); 4] = [
+        ("random", SamplerKind::Random),
+        ("stratified", SamplerKind::Stratified),
+        ("halton", SamplerKind::Halton),
+        ("sobol", SamplerKind::Sobol),
+    ];
+
+    fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, kind)| *kind)
+            .ok_or_else(|| format!("unknown sampler '{}'", s))
+    }
+}
+
+/// How the defocus and physical cameras pick their focus distance.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Focus {
+    /// Focus on the look-at point.
+    LookAt,
+    Distance(f32),
+    /// Focus on whatever is seen through pixel `(x, y)`, counted from the
+    /// top-left corner; `None` uses the centre of the frame.
+    Auto(Option<(i32, i32)>),
+}
+
+impl Focus {
+    fn parse(flag: &str, s: &str) -> Result<Self, String> {
+        match s {
+            "lookat" => Ok(Focus::LookAt),
+            "auto" => Ok(Focus::Auto(None)),
+            _ => Ok(Focus::Distance(parse_number(flag, s)?)),
+        }
+    }
+}
+
+fn parse_tile_order(s: &str) -> Result<TileOrder, String> {
+    match s {
+        "scanline" => Ok(TileOrder::Scanline),
+        "spiral" => Ok(TileOrder::Spiral),
+        "hilbert" => Ok(TileOrder::Hilbert),
+        _ => Err(format!("unknown tile order '{}'", s)),
+    }
+}
+
+fn parse_texture_filter(s: &str) -> Result<TextureFilter, String> {
+    match s {
+        "bilinear" => Ok(TextureFilter::Bilinear),
+        "trilinear" => Ok(TextureFilter::Trilinear),
+        "ewa" => Ok(TextureFilter::Ewa),
+        _ => Err(format!("unknown texture filter '{}'", s)),
+    }
+}
+
+/// Part of the frame to render, as `x0, y0, x1, y1` from the top-left corner.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Crop {
+    /// Pixel coordinates, with the far edges excluded.
+    Pixels(i32, i32, i32, i32),
+    /// Fractions of the frame's width and height.
+    Normalized(f32, f32, f32, f32),
+}
+
+impl Crop {
+    /// Pixels covered by the crop window, clipped to the frame. A normalised
+    /// window covers every pixel whose centre it contains.
+    pub fn region(self, width: i32, height: i32) -> Region {
+        let (x0, y0, x1, y1) = match self {
+            Crop::Pixels(x0, y0, x1, y1) => (x0, y0, x1, y1),
+            Crop::Normalized(x0, y0, x1, y1) => {
+                let edge = |t: f32, n: i32| (t * n as f32 - 0.5).ceil() as i32;
+                (
+                    edge(x0, width),
+                    edge(y0, height),
+                    edge(x1, width),
+                    edge(y1, height),
+                )
+            }
+        };
+        Region {
+            x0: x0.clamp(0, width),
+            y0: y0.clamp(0, height),
+            x1: x1.clamp(0, width),
+            y1: y1.clamp(0, height),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RenderSettings {
+    pub nx: i32,
+    pub ny: i32,
+    pub ns: i32,
+    pub camera: CameraKind,
+    pub stereo_layout: StereoLayout,
+    pub interocular: f32,
+    /// Zero-parallax distance of the stereo camera; `None` converges on `lookat`.
+    pub convergence: Option<f32>,
+    /// Number of aperture blades for the defocus camera; `None` keeps it round.
+    pub aperture_blades: Option<u32>,
+    pub aperture_rotation: f32,
+    /// Greyscale PGM image used as the aperture shape, overriding the blades.
+    pub aperture_mask: Option<String>,
+    pub vignetting: f32,
+    pub physical: PhysicalCamera,
+    pub focus: Focus,
+    /// Seeds both the scene layout and the per-pixel samplers.
+    pub seed: u64,
+    /// Seeds the scene layout instead of `seed`, so that renders of the same
+    /// scene with different sampler seeds can be merged.
+    pub scene_seed: Option<u64>,
+    pub sampler: SamplerKind,
+    /// Compare the noise of all samplers against a reference with this many
+    /// samples per pixel, instead of rendering an image.
+    pub measure_noise: Option<i32>,
+    /// Enables adaptive sampling: pixels stop once their relative noise falls
+    /// below this, and `ns` becomes the average number of samples per pixel.
+    pub noise_threshold: Option<f32>,
+    /// Samples every pixel gets, and the batch size, with adaptive sampling.
+    pub min_samples: i32,
+    /// PPM file to write the per-pixel sample counts to.
+    pub heatmap: Option<String>,
+    pub filter: Filter,
+    /// Filter radius in pixels; `None` uses the filter's default.
+    pub filter_radius: Option<f32>,
+    /// Exposure adjustment in stops, on top of the physical camera's exposure.
+    pub exposure: f32,
+    pub tone_map: ToneMap,
+    pub dither: bool,
+    /// Trace wavelengths instead of RGB, which makes dispersion visible.
+    pub spectral: bool,
+    pub scene: Scene,
+    /// PPM or PGM image for the textured scene, instead of a checkerboard.
+    pub texture: Option<String>,
+    pub texture_filter: TextureFilter,
+    /// Height map for the textured scene's mirror sphere, instead of dimples.
+    pub bump_map: Option<String>,
+    /// Height in scene units of a white bump map texel.
+    pub bump_scale: f32,
+    /// Tangent-space normal map for the mirror sphere, replacing the height
+    /// map.
+    pub normal_map: Option<String>,
+    pub integrator: IntegratorKind,
+    /// How far the ambient occlusion integrator looks for occluders.
+    pub ao_distance: f32,
+    /// Distance at which the depth integrator fades to black.
+    pub depth_range: f32,
+    pub path: PathLimits,
+    /// Directory to write each AOV to as a PFM image.
+    pub aov_dir: Option<String>,
+    /// OpenEXR file to write the beauty pass and AOVs to as layers.
+    pub aov_exr: Option<String>,
+    /// Run the AOV-guided denoiser on the final image.
+    pub denoise: bool,
+    /// Samples added to every pixel in each progressive pass.
+    pub pass_samples: i32,
+    /// File to save the render's progress to, for resuming it later.
+    pub checkpoint: Option<String>,
+    /// Seconds between checkpoints; one is always written at the end.
+    pub checkpoint_interval: f32,
+    /// Checkpoints to continue from. Several are merged into one first.
+    pub resume: Vec<String>,
+    /// Stop after this many seconds with the samples taken so far.
+    pub time_limit: Option<f32>,
+    /// Stop once the average relative noise of the pixels falls below this.
+    pub target_noise: Option<f32>,
+    /// Report the progress of every pass on stderr.
+    pub progress: bool,
+    /// Draw the image in progress on the terminal, on stderr.
+    pub preview: bool,
+    /// Print ray and intersection counts and phase timings on stderr.
+    pub stats: bool,
+    /// JSON file to write the same statistics to.
+    pub stats_json: Option<String>,
+    /// Render only this part of the frame.
+    pub crop: Option<Crop>,
+    /// Keep the full frame size when cropping, with black outside the crop
+    /// window, instead of writing just the cropped region.
+    pub full_frame: bool,
+    pub tile_order: TileOrder,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            nx: 200,
+            ny: 100,
+            ns: 10,
+            camera: CameraKind::NoBlur,
+            stereo_layout: StereoLayout::SideBySide,
+            interocular: 0.065,
+            convergence: None,
+            aperture_blades: None,
+            aperture_rotation: 0.0,
+            aperture_mask: None,
+            vignetting: 0.0,
+            physical: PhysicalCamera::default(),
+            focus: Focus::LookAt,
+            seed: 0,
+            scene_seed: None,
+            sampler: SamplerKind::Random,
+            measure_noise: None,
+            noise_threshold: None,
+            min_samples: 4,
+            heatmap: None,
+            filter: Filter::Box,
+            filter_radius: None,
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+            dither: false,
+            spectral: false,
+            scene: Scene::Random,
+            texture: None,
+            texture_filter: TextureFilter::Trilinear,
+            bump_map: None,
+            bump_scale: 0.02,
+            normal_map: None,
+            integrator: IntegratorKind::Path,
+            ao_distance: 1.0,
+            depth_range: 20.0,
+            path: PathLimits::default(),
+            aov_dir: None,
+            aov_exr: None,
+            denoise: false,
+            pass_samples: 1,
+            checkpoint: None,
+            checkpoint_interval: 60.0,
+            resume: Vec::new(),
+            time_limit: None,
+            target_noise: None,
+            progress: false,
+            preview: false,
+            stats: false,
+            stats_json: None,
+            crop: None,
+            full_frame: false,
+            tile_order: TileOrder::Scanline,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Reads `--flag value` pairs and `--switch` flags from the command line on
+    /// top of the defaults.
+    pub fn from_args() -> Result<Self, String> {
+        let mut settings = Self::default();
+        let mut args = env::args().skip(1);
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--dither" => {
+                    settings.dither = true;
+                    continue;
+                }
+                "--spectral" => {
+                    settings.spectral = true;
+                    continue;
+                }
+                "--denoise" => {
+                    settings.denoise = true;
+                    continue;
+                }
+                "--progress" => {
+                    settings.progress = true;
+                    continue;
+                }
+                "--preview" => {
+                    settings.preview = true;
+                    continue;
+                }
+                "--stats" => {
+                    settings.stats = true;
+                    continue;
+                }
+                "--full-frame" => {
+                    settings.full_frame = true;
+                    continue;
+                }
+                _ => {}
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", flag))?;
+            match flag.as_str() {
+                "--width" => settings.nx = parse_number(&flag, &value)?,
+                "--height" => settings.ny = parse_number(&flag, &value)?,
+                "--samples" => settings.ns = parse_number(&flag, &value)?,
+                "--camera" => settings.camera = CameraKind::parse(&value)?,
+                "--stereo-layout" => settings.stereo_layout = parse_stereo_layout(&value)?,
+                "--interocular" => settings.interocular = parse_number(&flag, &value)?,
+                "--convergence" => settings.convergence = Some(parse_number(&flag, &value)?),
+                "--aperture-blades" => {
+                    settings.aperture_blades = Some(parse_number(&flag, &value)?)
+                }
+                "--aperture-rotation" => settings.aperture_rotation = parse_number(&flag, &value)?,
+                "--aperture-mask" => settings.aperture_mask = Some(value),
+                "--vignetting" => settings.vignetting = parse_number(&flag, &value)?,
+                "--focal-length" => settings.physical.focal_length = parse_number(&flag, &value)?,
+                "--f-number" => settings.physical.f_number = parse_number(&flag, &value)?,
+                "--shutter" => settings.physical.shutter = parse_shutter(&flag, &value)?,
+                "--iso" => settings.physical.iso = parse_number(&flag, &value)?,
+                "--seed" => settings.seed = parse_number(&flag, &value)?,
+                "--scene-seed" => settings.scene_seed = Some(parse_number(&flag, &value)?),
+                "--sampler" => settings.sampler = SamplerKind::parse(&value)?,
+                "--noise-threshold" => {
+                    settings.noise_threshold = Some(parse_number(&flag, &value)?)
+                }
+                "--min-samples" => settings.min_samples = parse_number(&flag, &value)?,
+                "--heatmap" => settings.heatmap = Some(value),
+                "--filter" => settings.filter = parse_filter(&value)?,
+                "--filter-radius" => settings.filter_radius = Some(parse_number(&flag, &value)?),
+                "--exposure" => settings.exposure = parse_number(&flag, &value)?,
+                "--max-depth" => settings.path.max_depth = parse_number(&flag, &value)?,
+                "--max-diffuse" => settings.path.max_diffuse = parse_number(&flag, &value)?,
+                "--max-specular" => settings.path.max_specular = parse_number(&flag, &value)?,
+                "--max-transmission" => {
+                    settings.path.max_transmission = parse_number(&flag, &value)?
+                }
+                "--roulette-depth" => settings.path.roulette_depth = parse_number(&flag, &value)?,
+                "--aov-dir" => settings.aov_dir = Some(value),
+                "--aov-exr" => settings.aov_exr = Some(value),
+                "--pass-samples" => settings.pass_samples = parse_number(&flag, &value)?,
+                "--checkpoint" => settings.checkpoint = Some(value),
+                "--checkpoint-interval" => {
+                    settings.checkpoint_interval = parse_number(&flag, &value)?
+                }
+                "--resume" => settings.resume = value.split(',').map(String::from).collect(),
+                "--time-limit" => settings.time_limit = Some(parse_number(&flag, &value)?),
+                "--target-noise" => settings.target_noise = Some(parse_number(&flag, &value)?),
+                "--crop" => {
+                    let [x0, y0, x1, y1] = parse_corners(&flag, &value)?;
+                    settings.crop = Some(Crop::Pixels(x0, y0, x1, y1));
+                }
+                "--crop-window" => {
+                    let [x0, y0, x1, y1] = parse_corners(&flag, &value)?;
+                    settings.crop = Some(Crop::Normalized(x0, y0, x1, y1));
+                }
+                "--stats-json" => settings.stats_json = Some(value),
+                "--tile-order" => settings.tile_order = parse_tile_order(&value)?,
+                "--scene" => settings.scene = Scene::parse(&value)?,
+                "--texture" => settings.texture = Some(value),
+                "--texture-filter" => settings.texture_filter = parse_texture_filter(&value)?,
+                "--bump-map" => settings.bump_map = Some(value),
+                "--bump-scale" => settings.bump_scale = parse_number(&flag, &value)?,
+                "--normal-map" => settings.normal_map = Some(value),
+                "--integrator" => settings.integrator = IntegratorKind::parse(&value)?,
+                "--ao-distance" => settings.ao_distance = parse_number(&flag, &value)?,
+                "--depth-range" => settings.depth_range = parse_number(&flag, &value)?,
+                "--tone-map" => settings.tone_map = parse_tone_map(&value)?,
+                "--measure-noise" => settings.measure_noise = Some(parse_number(&flag, &value)?),
+                "--focus" => settings.focus = Focus::parse(&flag, &value)?,
+                "--focus-pixel" => {
+                    let (x, y) = value
+                        .split_once(',')
+                        .ok_or_else(|| format!("expected X,Y for {}", flag))?;
+                    settings.focus = Focus::Auto(Some((
+                        parse_number(&flag, x)?,
+                        parse_number(&flag, y)?,
+                    )));
+                }
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+        settings.check_ranges()?;
+        if let Some(crop) = settings.crop {
+            if crop.region(settings.nx, settings.ny).is_empty() {
+                return Err("the crop window does not cover any pixels".to_string());
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn wants_aovs(&self) -> bool {
+        self.aov_dir.is_some() || self.aov_exr.is_some() || self.denoise
+    }
+
+    /// Rejects values that would make the render meaningless or crash it,
+    /// such as an empty image.
+    fn check_ranges(&self) -> Result<(), String> {
+        at_least("--width", self.nx, 1)?;
+        at_least("--height", self.ny, 1)?;
+        // Zero samples is fine when only merging checkpoints.
+        at_least("--samples", self.ns, if self.resume.is_empty() { 1 } else { 0 })?;
+        at_least("--min-samples", self.min_samples, 1)?;
+        at_least("--pass-samples", self.pass_samples, 1)?;
+        at_least("--max-depth", self.path.max_depth, 1)?;
+        if let Some(n) = self.measure_noise {
+            at_least("--measure-noise", n, 1)?;
+        }
+        positive("--ao-distance", self.ao_distance)?;
+        positive("--depth-range", self.depth_range)?;
+        positive("--focal-length", self.physical.focal_length)?;
+        positive("--f-number", self.physical.f_number)?;
+        positive("--shutter", self.physical.shutter)?;
+        positive("--iso", self.physical.iso)?;
+        finite("--exposure", self.exposure)?;
+        finite("--aperture-rotation", self.aperture_rotation)?;
+        finite("--bump-scale", self.bump_scale)?;
+        non_negative("--interocular", self.interocular)?;
+        if let Some(blades) = self.aperture_blades {
+            at_least("--aperture-blades", blades, 3)?;
+        }
+        non_negative("--vignetting", self.vignetting)?;
+        match self.focus {
+            Focus::Distance(d) => positive("--focus", d)?,
+            Focus::Auto(Some((x, y))) if x < 0 || x >= self.nx || y < 0 || y >= self.ny => {
+                return Err(format!(
+                    "--focus-pixel {},{} is outside the {}x{} frame",
+                    x, y, self.nx, self.ny
+                ));
+            }
+            _ => {}
+        }
+        non_negative("--checkpoint-interval", self.checkpoint_interval)?;
+        let optional = [
+            ("--convergence", self.convergence),
+            ("--filter-radius", self.filter_radius),
+            ("--noise-threshold", self.noise_threshold),
+            ("--time-limit", self.time_limit),
+            ("--target-noise", self.target_noise),
+        ];
+        for &(flag, value) in optional.iter() {
+            if let Some(value) = value {
+                positive(flag, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Accepts seconds either as a decimal or in the photographic "1/250" form.
+fn parse_shutter(flag: &str, value: &str) -> Result<f32, String> {
+    match value.split_once('/') {
+        Some((num, den)) => {
+            Ok(parse_number::<f32>(flag, num)? / parse_number::<f32>(flag, den)?)
+        }
+        None => parse_number(flag, value),
+    }
+}
+
+/// Reads the `X0,Y0,X1,Y1` corners of a crop window.
+fn parse_corners<T: std::str::FromStr>(flag: &str, value: &str) -> Result<[T; 4], String> {
+    let corners = value
+        .split(',')
+        .map(|s| parse_number(flag, s))
+        .collect::<Result<Vec<T>, String>>()?;
+    <[T; 4]>::try_from(corners).map_err(|_| format!("expected X0,Y0,X1,Y1 for {}", flag))
+}
+
+fn at_least<T>(flag: &str, value: T, min: T) -> Result<(), String>
+where
+    T: PartialOrd + std::fmt::Display,
+{
+    if value >= min {
+        Ok(())
+    } else {
+        Err(format!("{} must be at least {}, got {}", flag, min, value))
+    }
+}
+
+fn finite(flag: &str, value: f32) -> Result<(), String> {
+    if value.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("{} must be finite, got {}", flag, value))
+    }
+}
+
+fn positive(flag: &str, value: f32) -> Result<(), String> {
+    finite(flag, value)?;
+    if value > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("{} must be positive, got {}", flag, value))
+    }
+}
+
+fn non_negative(flag: &str, value: f32) -> Result<(), String> {
+    finite(flag, value)?;
+    if value >= 0.0 {
+        Ok(())
+    } else {
+        Err(format!("{} must not be negative, got {}", flag, value))
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
@@ -0,0 +1,124 @@
+use super::vec3;
+use std::sync::OnceLock;
+
+/// Range of wavelengths, in nanometres, sampled by spectral renders.
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 780.0;
+
+/// Number of wavelengths carried by each path (hero wavelength sampling).
+pub const WAVELENGTHS: usize = 4;
+
+/// Picks `WAVELENGTHS` equally spaced wavelengths from one uniform sample, so
+/// that every path covers the visible range.
+pub fn sample_wavelengths(u: f32) -> [f32; WAVELENGTHS] {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let mut wavelengths = [0.0; WAVELENGTHS];
+    for (k, lambda) in wavelengths.iter_mut().enumerate() {
+        let offset = (u + k as f32 / WAVELENGTHS as f32).fract();
+        *lambda = LAMBDA_MIN + offset * range;
+    }
+    wavelengths
+}
+
+fn lobe(x: f32, mu: f32, sigma_below: f32, sigma_above: f32) -> f32 {
+    let sigma = if x < mu { sigma_below } else { sigma_above };
+    let t = (x - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 colour matching functions, using the multi-lobe Gaussian fit of
+/// Wyman, Sloan and Shirley (2013).
+pub fn cie_xyz(lambda: f32) -> vec3::Vec3 {
+    vec3::Vec3::new(
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_linear_srgb(xyz: vec3::Vec3) -> vec3::Vec3 {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    vec3::Vec3::new(
+        3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z,
+        -0.969_266 * x + 1.876_010_8 * y + 0.041_556 * z,
+        0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z,
+    )
+}
+
+/// Per-channel scale that maps a constant unit spectrum to RGB white, which
+/// balances the equal-energy white point of the upsampled spectra.
+fn white_balance() -> vec3::Vec3 {
+    static WHITE: OnceLock<[f32; 3]> = OnceLock::new();
+    let white = WHITE.get_or_init(|| {
+        let steps = 4000;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f32;
+        let mut sum = vec3::Vec3::new(0, 0, 0);
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + (i as f32 + 0.5) * step;
+            sum += xyz_to_linear_srgb(cie_xyz(lambda));
+        }
+        sum /= steps as f32;
+        [1.0 / sum.r(), 1.0 / sum.g(), 1.0 / sum.b()]
+    });
+    vec3::Vec3 { e: *white }
+}
+
+/// Converts radiance samples at the given wavelengths, each drawn uniformly
+/// over the visible range, to an RGB estimate.
+pub fn to_rgb(wavelengths: &[f32; WAVELENGTHS], radiance: &[f32; WAVELENGTHS]) -> vec3::Vec3 {
+    let mut xyz = vec3::Vec3::new(0, 0, 0);
+    for (&lambda, &l) in wavelengths.iter().zip(radiance.iter()) {
+        xyz += l * cie_xyz(lambda);
+    }
+    xyz /= WAVELENGTHS as f32;
+    xyz_to_linear_srgb(xyz) * white_balance()
+}
+
+// Smits' (1999) basis spectra for RGB upsampling, in 10 bins from 380 to 720 nm.
+const WHITE: [f32; 10] = [1.0, 1.0, 0.9999, 0.9993, 0.9992, 0.9998, 1.0, 1.0, 1.0, 1.0];
+const CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0, 0.0, 0.0];
+const MAGENTA: [f32; 10] = [1.0, 1.0, 0.9685, 0.2229, 0.0, 0.0458, 0.8369, 1.0, 1.0, 0.9959];
+const YELLOW: [f32; 10] = [0.0001, 0.0, 0.1088, 0.6651, 1.0, 1.0, 0.9996, 0.9586, 0.9685, 0.9840];
+const RED: [f32; 10] = [0.1012, 0.0515, 0.0, 0.0, 0.0, 0.0, 0.8325, 1.0149, 1.0149, 1.0149];
+const GREEN: [f32; 10] = [0.0, 0.0, 0.0273, 0.7937, 1.0, 0.9418, 0.1719, 0.0, 0.0, 0.0025];
+const BLUE: [f32; 10] = [1.0, 1.0, 0.8916, 0.3323, 0.0, 0.0, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// Linearly interpolates a basis spectrum between its bin centres.
+fn basis(spectrum: &[f32; 10], lambda: f32) -> f32 {
+    let x = ((lambda - 380.0) / 34.0 - 0.5).clamp(0.0, 9.0);
+    let i = (x as usize).min(8);
+    let t = x - i as f32;
+    spectrum[i] * (1.0 - t) + spectrum[i + 1] * t
+}
+
+/// Value at `lambda` of a smooth spectrum whose colour is `rgb`, using Smits'
+/// method. Works for reflectances and, scaled, for illuminants.
+pub fn rgb_to_spectrum(rgb: vec3::Vec3, lambda: f32) -> f32 {
+    let (r, g, b) = (rgb.r(), rgb.g(), rgb.b());
+    if r <= g && r <= b {
+        let mut v = r * basis(&WHITE, lambda);
+        if g <= b {
+            v += (g - r) * basis(&CYAN, lambda) + (b - g) * basis(&BLUE, lambda);
+        } else {
+            v += (b - r) * basis(&CYAN, lambda) + (g - b) * basis(&GREEN, lambda);
+        }
+        v
+    } else if g <= r && g <= b {
+        let mut v = g * basis(&WHITE, lambda);
+        if r <= b {
+            v += (r - g) * basis(&MAGENTA, lambda) + (b - r) * basis(&BLUE, lambda);
+        } else {
+            v += (b - g) * basis(&MAGENTA, lambda) + (r - b) * basis(&RED, lambda);
+        }
+        v
+    } else {
+        let mut v = b * basis(&WHITE, lambda);
+        if r <= g {
+            v += (r - b) * basis(&YELLOW, lambda) + (g - r) * basis(&GREEN, lambda);
+        } else {
+            v += (g - b) * basis(&YELLOW, lambda) + (r - g) * basis(&RED, lambda);
+        }
+        v
+    }
+}
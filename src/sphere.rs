@@ -1,7 +1,10 @@
 use super::hitable;
 use super::material;
 use super::ray;
+use super::sampler;
+use super::stats;
 use super::vec3;
+use std::f32::consts::PI;
 
 pub struct Sphere {
     pub center: vec3::Vec3,
@@ -9,51 +12,185 @@ pub struct Sphere {
     pub material: Box<dyn material::Material>,
 }
 
+impl Sphere {
+    /// Builds the hit record at ray parameter `t`. The sphere is parameterised
+    /// by longitude `u`, starting at -x and turning towards +z, and by `v`
+    /// running from the bottom pole to the top one.
+    fn record(&self, r: ray::Ray, t: f32) -> hitable::HitRecord<'_> {
+        let p = r.point_at_parameter(t);
+        let (normal, front_face) =
+            hitable::HitRecord::face_normal(r, (p - self.center) / self.radius);
+        let d = (p - self.center) / self.radius.abs();
+        let (x, y, z) = (d.x(), d.y(), d.z());
+        let radius = self.radius.abs();
+        let sin_theta = (1.0 - y * y).max(1e-6).sqrt();
+        let dpdu = 2.0 * PI * radius * vec3::Vec3::new(z, 0.0, -x);
+        let dpdv = PI * radius * vec3::Vec3::new(-x * y / sin_theta, sin_theta, -y * z / sin_theta);
+        // The normal is +-d, so it turns with the surface scaled by 1/radius.
+        let side = normal.dot(d).signum() / radius;
+        hitable::HitRecord {
+            t,
+            p,
+            normal,
+            geometric_normal: normal,
+            front_face,
+            u: ((-z).atan2(x) + PI) / (2.0 * PI),
+            v: (-y).clamp(-1.0, 1.0).acos() / PI,
+            dpdu,
+            dpdv,
+            dndu: side * dpdu,
+            dndv: side * dpdv,
+            dudx: 0.0,
+            dvdx: 0.0,
+            dudy: 0.0,
+            dvdy: 0.0,
+            material: self.material.as_ref(),
+            object_id: 0,
+            primitive_id: 0,
+        }
+    }
+}
+
 impl hitable::Hitable for Sphere {
-    fn hit(&self, r: ray::Ray, t_min: f32, t_max: f32) -> Option<hitable::HitRecord> {
+    fn hit(&self, r: ray::Ray, t_min: f32, t_max: f32) -> Option<hitable::HitRecord<'_>> {
+        stats::count_hit(stats::Primitive::Sphere);
         let oc = r.origin() - self.center;
         let a = r.direction().dot(r.direction());
         let b = oc.dot(r.direction());
         let c = oc.dot(oc) - self.radius * self.radius;
         let discriminant = b * b - a * c;
         if discriminant > 0.0 {
-            let mut temp = (-b - discriminant.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                let p = r.point_at_parameter(temp);
-                return Some(hitable::HitRecord {
-                    t: temp,
-                    p: p,
-                    normal: (p - self.center) / self.radius,
-                    material: &self.material,
-                });
-            }
-            temp = (-b + discriminant.sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                let p = r.point_at_parameter(temp);
-                return Some(hitable::HitRecord {
-                    t: temp,
-                    p: p,
-                    normal: (p - self.center) / self.radius,
-                    material: &self.material,
-                });
+            let root = discriminant.sqrt();
+            for &t in [(-b - root) / a, (-b + root) / a].iter() {
+                if t < t_max && t > t_min {
+                    return Some(self.record(r, t));
+                }
             }
         }
-        return None;
+        None
+    }
+
+    fn occluded(&self, r: ray::Ray, t_min: f32, t_max: f32) -> bool {
+        stats::count_hit(stats::Primitive::Sphere);
+        let oc = r.origin() - self.center;
+        let a = r.direction().dot(r.direction());
+        let b = oc.dot(r.direction());
+        let c = oc.dot(oc) - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant <= 0.0 {
+            return false;
+        }
+        let root = discriminant.sqrt();
+        [(-b - root) / a, (-b + root) / a]
+            .iter()
+            .any(|&t| t < t_max && t > t_min)
     }
+
+    /// A sphere with a negative radius is inside out: its solid is everything
+    /// but the ball.
+    fn spans(&self, r: ray::Ray) -> Option<Vec<hitable::Span<'_>>> {
+        let oc = r.origin() - self.center;
+        let a = r.direction().dot(r.direction());
+        let b = oc.dot(r.direction());
+        let c = oc.dot(oc) - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        let infinity = |t: f32| hitable::Crossing { t, rec: None };
+        if discriminant <= 0.0 {
+            return Some(if self.radius < 0.0 {
+                vec![hitable::Span {
+                    enter: infinity(f32::NEG_INFINITY),
+                    exit: infinity(f32::INFINITY),
+                }]
+            } else {
+                Vec::new()
+            });
+        }
+        let root = discriminant.sqrt();
+        let crossing = |t: f32| hitable::Crossing {
+            t,
+            rec: Some(self.record(r, t)),
+        };
+        let (near, far) = (crossing((-b - root) / a), crossing((-b + root) / a));
+        Some(if self.radius < 0.0 {
+            vec![
+                hitable::Span {
+                    enter: infinity(f32::NEG_INFINITY),
+                    exit: near,
+                },
+                hitable::Span {
+                    enter: far,
+                    exit: infinity(f32::INFINITY),
+                },
+            ]
+        } else {
+            vec![hitable::Span {
+                enter: near,
+                exit: far,
+            }]
+        })
+    }
+}
+
+/// Maps three sample dimensions directly to a uniform point in the unit ball, so
+/// every call consumes the same dimensions of the sampler.
+pub fn random_in_unit_sphere(sampler: &mut dyn sampler::Sampler) -> vec3::Vec3 {
+    let (u, v) = sampler.next_2d();
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    let radius = sampler.next_1d().cbrt();
+    radius * vec3::Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn random_in_unit_sphere() -> vec3::Vec3 {
-    let mut p: vec3::Vec3;
-    loop {
-        p =
-            2.0 * vec3::Vec3::new(
-                    rand::random::<f32>(),
-                    rand::random::<f32>(),
-                    rand::random::<f32>(),
-                ) - vec3::Vec3::new(1, 1, 1);
-        if !(p.squared_length() >= 1.0) {
-            break;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitable::Hitable;
+
+    #[test]
+    fn occluded_agrees_with_hit() {
+        for &radius in [1.0, -1.0].iter() {
+            let sphere = Sphere {
+                center: vec3::Vec3::new(0, 0, 0),
+                radius,
+                material: Box::new(material::Lambertian {
+                    albedo: vec3::Vec3::new(0.5, 0.5, 0.5),
+                }),
+            };
+            // From outside, from the centre, and grazing past the edge.
+            for (case, &(origin, direction)) in [
+                (vec3::Vec3::new(0, 0, -5), vec3::Vec3::new(0, 0, 1)),
+                (vec3::Vec3::new(0, 0, 0), vec3::Vec3::new(1, 2, 3)),
+                (vec3::Vec3::new(0.5, 0, -5), vec3::Vec3::new(0, 0, 2)),
+                (vec3::Vec3::new(2, 0, -5), vec3::Vec3::new(0, 0, 1)),
+            ]
+            .iter()
+            .enumerate()
+            {
+                let r = ray::Ray {
+                    a: origin,
+                    b: direction,
+                };
+                for &(t_min, t_max) in [
+                    (0.001, f32::MAX),
+                    (0.001, 4.5),
+                    (4.5, 5.5),
+                    (5.5, f32::MAX),
+                    (-10.0, 0.0),
+                ]
+                .iter()
+                {
+                    assert_eq!(
+                        sphere.occluded(r, t_min, t_max),
+                        sphere.hit(r, t_min, t_max).is_some(),
+                        "radius {} ray {} t {}..{}",
+                        radius,
+                        case,
+                        t_min,
+                        t_max
+                    );
+                }
+            }
         }
     }
-    p
 }
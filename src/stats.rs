@@ -0,0 +1,119 @@
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Kinds of `Hitable` whose `hit` calls are counted separately.
+#[derive(Copy, Clone)]
+pub enum Primitive {
+    Sphere,
+    HitableList,
+    Csg,
+}
+
+impl Primitive {
+    const COUNT: usize = 3;
+    const NAMES: [&'static str; Primitive::COUNT] = ["sphere", "hitable_list", "csg"];
+}
+
+/// Render statistics. They are only collected after `enable`, and each thread
+/// counts into its own copy, so tracing never waits on a shared counter.
+/// There is no BVH yet, so there are no BVH node visits to count.
+#[derive(Clone, Default)]
+pub struct Counters {
+    pub primary_rays: u64,
+    /// Rays traced after the first bounce of a camera path.
+    pub secondary_rays: u64,
+    pub hit_calls: [u64; Primitive::COUNT],
+    /// Wall-clock time of each phase of the run, in order.
+    pub phases: Vec<(&'static str, Duration)>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static COUNTERS: RefCell<Counters> = RefCell::new(Counters::default());
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn update(f: impl FnOnce(&mut Counters)) {
+    if ENABLED.load(Ordering::Relaxed) {
+        COUNTERS.with(|counters| f(&mut counters.borrow_mut()));
+    }
+}
+
+pub fn count_hit(primitive: Primitive) {
+    update(|counters| counters.hit_calls[primitive as usize] += 1);
+}
+
+/// Counts the rays of one camera path, including the camera ray itself.
+pub fn count_path(rays: u64) {
+    update(|counters| {
+        counters.primary_rays += 1;
+        counters.secondary_rays += rays.saturating_sub(1);
+    });
+}
+
+pub fn record_phase(name: &'static str, time: Duration) {
+    update(|counters| counters.phases.push((name, time)));
+}
+
+/// The statistics collected on this thread.
+pub fn collect() -> Counters {
+    COUNTERS.with(|counters| counters.borrow().clone())
+}
+
+impl Counters {
+    /// Average number of rays along a camera path.
+    pub fn average_path_length(&self) -> f64 {
+        (self.primary_rays + self.secondary_rays) as f64 / self.primary_rays.max(1) as f64
+    }
+
+    pub fn table(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{:<24} {:>14}", "primary rays", self.primary_rays);
+        let _ = writeln!(out, "{:<24} {:>14}", "secondary rays", self.secondary_rays);
+        let _ = writeln!(
+            out,
+            "{:<24} {:>14.3}",
+            "average path length",
+            self.average_path_length()
+        );
+        for (name, calls) in Primitive::NAMES.iter().zip(self.hit_calls.iter()) {
+            let _ = writeln!(out, "{:<24} {:>14}", format!("{} hit calls", name), calls);
+        }
+        for (name, time) in &self.phases {
+            let _ = writeln!(
+                out,
+                "{:<24} {:>13.3}s",
+                format!("{} time", name),
+                time.as_secs_f64()
+            );
+        }
+        out
+    }
+
+    pub fn json(&self) -> String {
+        let hit_calls: Vec<String> = Primitive::NAMES
+            .iter()
+            .zip(self.hit_calls.iter())
+            .map(|(name, calls)| format!("\"{}\": {}", name, calls))
+            .collect();
+        let phases: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(name, time)| format!("\"{}\": {}", name, time.as_secs_f64()))
+            .collect();
+        format!(
+            "{{\n  \"primary_rays\": {},\n  \"secondary_rays\": {},\n  \"average_path_length\": {},\n  \"hit_calls\": {{{}}},\n  \"phase_seconds\": {{{}}}\n}}\n",
+            self.primary_rays,
+            self.secondary_rays,
+            self.average_path_length(),
+            hit_calls.join(", "),
+            phases.join(", ")
+        )
+    }
+}
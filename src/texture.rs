@@ -0,0 +1,266 @@
+use super::hitable;
+use super::pnm;
+use super::tonemap;
+use super::vec3;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// How image textures are filtered over a pixel's footprint.
+#[derive(Copy, Clone, PartialEq)]
+pub enum TextureFilter {
+    /// Bilinear interpolation of the full-resolution image, ignoring the
+    /// footprint. Distant or grazing textures alias.
+    Bilinear,
+    /// Bilinear lookups in the two MIP levels closest to the footprint's
+    /// largest extent, blended linearly. Fast, but blurs textures seen at
+    /// grazing angles.
+    Trilinear,
+    /// Elliptically weighted average over the footprint's ellipse, in the
+    /// MIP level that matches its minor axis.
+    Ewa,
+}
+
+/// Longest ratio between the axes of an EWA ellipse. Thinner ellipses are
+/// widened, trading some blur for a bounded number of texel fetches.
+const MAX_ANISOTROPY: f32 = 8.0;
+
+struct Level {
+    width: usize,
+    height: usize,
+    texels: Vec<vec3::Vec3>,
+}
+
+impl Level {
+    /// Texel at integer coordinates, wrapping around the edges.
+    fn texel(&self, x: i32, y: i32) -> vec3::Vec3 {
+        let x = x.rem_euclid(self.width as i32) as usize;
+        let y = y.rem_euclid(self.height as i32) as usize;
+        self.texels[y * self.width + x]
+    }
+
+    /// Bilinear interpolation at `(s, t)`, measured in image widths and
+    /// heights from the top-left corner.
+    fn bilinear(&self, s: f32, t: f32) -> vec3::Vec3 {
+        let x = s * self.width as f32 - 0.5;
+        let y = t * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        (1.0 - fx) * (1.0 - fy) * self.texel(x0, y0)
+            + fx * (1.0 - fy) * self.texel(x0 + 1, y0)
+            + (1.0 - fx) * fy * self.texel(x0, y0 + 1)
+            + fx * fy * self.texel(x0 + 1, y0 + 1)
+    }
+
+    /// Gaussian-weighted average of the texels inside the ellipse centred at
+    /// `(s, t)` with conjugate radii `ds0` and `ds1`, all in image units.
+    fn ewa(&self, s: f32, t: f32, ds0: (f32, f32), ds1: (f32, f32)) -> vec3::Vec3 {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let (s, t) = (s * w - 0.5, t * h - 0.5);
+        let ds0 = (ds0.0 * w, ds0.1 * h);
+        let ds1 = (ds1.0 * w, ds1.1 * h);
+
+        // Implicit ellipse a x^2 + b x y + c y^2 < 1, grown by a texel so it
+        // never falls between texel centres.
+        let mut a = ds0.1 * ds0.1 + ds1.1 * ds1.1 + 1.0;
+        let mut b = -2.0 * (ds0.0 * ds0.1 + ds1.0 * ds1.1);
+        let mut c = ds0.0 * ds0.0 + ds1.0 * ds1.0 + 1.0;
+        let inv_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+
+        // Bounding box of the ellipse.
+        let det = -b * b + 4.0 * a * c;
+        let s_extent = 2.0 * (det * c).sqrt() / det;
+        let t_extent = 2.0 * (det * a).sqrt() / det;
+        let (s0, s1) = ((s - s_extent).ceil() as i32, (s + s_extent).floor() as i32);
+        let (t0, t1) = ((t - t_extent).ceil() as i32, (t + t_extent).floor() as i32);
+
+        let alpha: f32 = 2.0;
+        let mut sum = vec3::Vec3::new(0, 0, 0);
+        let mut weights = 0.0;
+        for y in t0..=t1 {
+            let dt = y as f32 - t;
+            for x in s0..=s1 {
+                let ds = x as f32 - s;
+                let r2 = a * ds * ds + b * ds * dt + c * dt * dt;
+                if r2 < 1.0 {
+                    let weight = (-alpha * r2).exp() - (-alpha).exp();
+                    sum += weight * self.texel(x, y);
+                    weights += weight;
+                }
+            }
+        }
+        if weights <= 0.0 {
+            return self.bilinear((s + 0.5) / w, (t + 0.5) / h);
+        }
+        sum / weights
+    }
+}
+
+/// Image pyramid in linear RGB. Each level halves the one before, averaging
+/// blocks of 2x2 texels, down to a single texel.
+pub struct Mipmap {
+    levels: Vec<Level>,
+}
+
+impl Mipmap {
+    pub fn new(width: usize, height: usize, texels: Vec<vec3::Vec3>) -> Self {
+        let mut levels = vec![Level {
+            width,
+            height,
+            texels,
+        }];
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            let width = (last.width / 2).max(1);
+            let height = (last.height / 2).max(1);
+            let mut texels = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    // Odd sizes drop their last row or column.
+                    let (x0, y0) = (2 * x as i32, 2 * y as i32);
+                    texels.push(
+                        0.25 * (last.texel(x0, y0)
+                            + last.texel(x0 + 1, y0)
+                            + last.texel(x0, y0 + 1)
+                            + last.texel(x0 + 1, y0 + 1)),
+                    );
+                }
+            }
+            levels.push(Level {
+                width,
+                height,
+                texels,
+            });
+        }
+        Self { levels }
+    }
+
+    /// Loads a PPM or PGM image, taking its values to be sRGB encoded.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::load_with(path, tonemap::srgb_eotf)
+    }
+
+    /// Loads a PPM or PGM image of data rather than colours, such as a normal
+    /// or height map, keeping its values as they are.
+    pub fn load_data<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::load_with(path, |x| x)
+    }
+
+    fn load_with<P: AsRef<Path>>(path: P, decode: impl Fn(f32) -> f32) -> io::Result<Self> {
+        let image = pnm::read(path)?;
+        let texels = image
+            .values
+            .chunks(image.channels)
+            .map(|c| {
+                if image.channels == 1 {
+                    vec3::Vec3::new(decode(c[0]), decode(c[0]), decode(c[0]))
+                } else {
+                    vec3::Vec3::new(decode(c[0]), decode(c[1]), decode(c[2]))
+                }
+            })
+            .collect();
+        Ok(Self::new(image.width, image.height, texels))
+    }
+
+    /// Average colour of the whole image.
+    pub fn average(&self) -> vec3::Vec3 {
+        self.levels[self.levels.len() - 1].texels[0]
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    /// Number of level 0 texels along the longer side of the image.
+    fn resolution(&self) -> f32 {
+        self.levels[0].width.max(self.levels[0].height) as f32
+    }
+
+    /// Filtered value at `(s, t)`, in image widths and heights from the
+    /// top-left corner. `dsdx` and `dsdy` are the changes of `(s, t)` towards
+    /// the neighbouring pixels in x and y.
+    pub fn lookup(
+        &self,
+        s: f32,
+        t: f32,
+        dsdx: (f32, f32),
+        dsdy: (f32, f32),
+        filter: TextureFilter,
+    ) -> vec3::Vec3 {
+        let last = (self.levels.len() - 1) as f32;
+        match filter {
+            TextureFilter::Bilinear => self.levels[0].bilinear(s, t),
+            TextureFilter::Trilinear => {
+                let extent = [dsdx.0, dsdx.1, dsdy.0, dsdy.1]
+                    .iter()
+                    .fold(0.0f32, |m, d| m.max(d.abs()));
+                let level = (2.0 * extent * self.resolution()).max(1e-8).log2();
+                let level = level.clamp(0.0, last);
+                self.blend(level, |l| l.bilinear(s, t))
+            }
+            TextureFilter::Ewa => {
+                let length = |d: (f32, f32)| (d.0 * d.0 + d.1 * d.1).sqrt();
+                let (mut major, mut minor) = (dsdx, dsdy);
+                if length(major) < length(minor) {
+                    std::mem::swap(&mut major, &mut minor);
+                }
+                let (major_length, mut minor_length) = (length(major), length(minor));
+                if minor_length == 0.0 {
+                    return self.levels[0].bilinear(s, t);
+                }
+                if minor_length * MAX_ANISOTROPY < major_length {
+                    let scale = major_length / (minor_length * MAX_ANISOTROPY);
+                    minor = (minor.0 * scale, minor.1 * scale);
+                    minor_length *= scale;
+                }
+                let level = (minor_length * self.resolution()).log2().max(0.0);
+                // Also catches infinite footprints from rays grazing the surface.
+                if level.is_nan() || level >= last {
+                    return self.average();
+                }
+                self.blend(level, |l| l.ewa(s, t, major, minor))
+            }
+        }
+    }
+
+    /// Linear blend of `lookup` in the two levels around fractional `level`.
+    fn blend(&self, level: f32, lookup: impl Fn(&Level) -> vec3::Vec3) -> vec3::Vec3 {
+        let lower = level.floor() as usize;
+        let f = level - lower as f32;
+        if lower + 1 >= self.levels.len() || f == 0.0 {
+            return lookup(&self.levels[lower]);
+        }
+        (1.0 - f) * lookup(&self.levels[lower]) + f * lookup(&self.levels[lower + 1])
+    }
+}
+
+/// An image mapped onto a surface's `(u, v)` coordinates, with `u` running
+/// across the image and `v` up it.
+#[derive(Clone)]
+pub struct ImageTexture {
+    pub mipmap: Arc<Mipmap>,
+    /// Number of times the image repeats across `u` and `v`.
+    pub repeat: (f32, f32),
+    pub filter: TextureFilter,
+}
+
+impl ImageTexture {
+    pub fn value(&self, rec: &hitable::HitRecord) -> vec3::Vec3 {
+        let (su, sv) = self.repeat;
+        // Images are stored top row first, while `v` counts upwards.
+        self.mipmap.lookup(
+            rec.u * su,
+            1.0 - rec.v * sv,
+            (rec.dudx * su, -rec.dvdx * sv),
+            (rec.dudy * su, -rec.dvdy * sv),
+            self.filter,
+        )
+    }
+}
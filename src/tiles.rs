@@ -0,0 +1,170 @@
+use std::f32::consts::PI;
+
+/// Side of the square tiles that the frame is visited in, in pixels.
+pub const TILE_SIZE: i32 = 16;
+
+/// Rectangle of pixels from `(x0, y0)` up to but excluding `(x1, y1)`, counted
+/// from the top-left corner.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Region {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+}
+
+impl Region {
+    pub fn full(width: i32, height: i32) -> Self {
+        Self {
+            x0: 0,
+            y0: 0,
+            x1: width,
+            y1: height,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        (self.x1 - self.x0).max(0)
+    }
+
+    pub fn height(&self) -> i32 {
+        (self.y1 - self.y0).max(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width() == 0 || self.height() == 0
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x0 && x < self.x1 && y >= self.y0 && y < self.y1
+    }
+
+    /// Grows the region by `margin` pixels on every side, without leaving the
+    /// `width` by `height` frame.
+    pub fn expand(&self, margin: i32, width: i32, height: i32) -> Self {
+        Self {
+            x0: (self.x0 - margin).max(0),
+            y0: (self.y0 - margin).max(0),
+            x1: (self.x1 + margin).min(width),
+            y1: (self.y1 + margin).min(height),
+        }
+    }
+
+    /// Cuts the region out of per-pixel data for a frame `width` pixels wide,
+    /// stored top row first.
+    pub fn crop<T: Clone>(&self, data: &[T], width: i32) -> Vec<T> {
+        (self.y0..self.y1)
+            .flat_map(|y| {
+                let row = (y * width) as usize;
+                data[row + self.x0 as usize..row + self.x1 as usize].iter().cloned()
+            })
+            .collect()
+    }
+
+    /// Sets the per-pixel data outside the region to `value`, for a frame
+    /// `width` pixels wide stored top row first.
+    pub fn mask<T: Clone>(&self, data: &mut [T], width: i32, value: T) {
+        for (index, item) in data.iter_mut().enumerate() {
+            if !self.contains(index as i32 % width, index as i32 / width) {
+                *item = value.clone();
+            }
+        }
+    }
+}
+
+/// Order in which the tiles of a frame are rendered.
+#[derive(Copy, Clone, PartialEq)]
+pub enum TileOrder {
+    /// Rows of tiles from the top.
+    Scanline,
+    /// Outwards from the centre, where the subject usually is.
+    Spiral,
+    /// Along a Hilbert curve, which keeps consecutive tiles next to each other.
+    Hilbert,
+}
+
+/// Indices of the pixels in `region` of a frame `width` pixels wide, tile by
+/// tile in the given order and row by row within each tile.
+pub fn pixel_order(region: Region, order: TileOrder, width: i32) -> Vec<usize> {
+    let tiles_x = (region.width() + TILE_SIZE - 1) / TILE_SIZE;
+    let tiles_y = (region.height() + TILE_SIZE - 1) / TILE_SIZE;
+    let mut tiles: Vec<(i32, i32)> = Vec::new();
+    match order {
+        TileOrder::Scanline => {
+            for ty in 0..tiles_y {
+                for tx in 0..tiles_x {
+                    tiles.push((tx, ty));
+                }
+            }
+        }
+        TileOrder::Spiral => {
+            for ty in 0..tiles_y {
+                for tx in 0..tiles_x {
+                    tiles.push((tx, ty));
+                }
+            }
+            // Rings of tiles around the centre, each walked clockwise from
+            // the top.
+            let cx = (tiles_x - 1) as f32 / 2.0;
+            let cy = (tiles_y - 1) as f32 / 2.0;
+            let key = |&(tx, ty): &(i32, i32)| {
+                let (dx, dy) = (tx as f32 - cx, ty as f32 - cy);
+                let ring = dx.abs().max(dy.abs()).round() as i32;
+                let angle = (dx.atan2(-dy) + 2.0 * PI) % (2.0 * PI);
+                (ring, angle)
+            };
+            tiles.sort_by(|a, b| {
+                let (ka, kb) = (key(a), key(b));
+                ka.0.cmp(&kb.0).then(ka.1.partial_cmp(&kb.1).unwrap())
+            });
+        }
+        TileOrder::Hilbert => {
+            let mut n = 1;
+            while n < tiles_x.max(tiles_y) {
+                n *= 2;
+            }
+            for d in 0..n * n {
+                let (tx, ty) = hilbert_point(n, d);
+                if tx < tiles_x && ty < tiles_y {
+                    tiles.push((tx, ty));
+                }
+            }
+        }
+    }
+
+    let mut pixels = Vec::with_capacity((region.width() * region.height()) as usize);
+    for (tx, ty) in tiles {
+        let x0 = region.x0 + tx * TILE_SIZE;
+        let y0 = region.y0 + ty * TILE_SIZE;
+        for y in y0..(y0 + TILE_SIZE).min(region.y1) {
+            for x in x0..(x0 + TILE_SIZE).min(region.x1) {
+                pixels.push((y * width + x) as usize);
+            }
+        }
+    }
+    pixels
+}
+
+/// Point at distance `d` along the Hilbert curve filling an `n` by `n` grid,
+/// where `n` is a power of two.
+fn hilbert_point(n: i32, d: i32) -> (i32, i32) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
@@ -0,0 +1,161 @@
+use super::render;
+use super::vec3;
+
+/// Operators that compress scene-referred radiance into [0, 1] display values.
+#[derive(Copy, Clone, PartialEq)]
+pub enum ToneMap {
+    /// No compression; anything above 1 clips.
+    Clamp,
+    /// Reinhard on luminance, which keeps hue and saturation.
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference and sRGB output transforms.
+    Aces,
+    /// Minimal AgX: a log encoding with a sigmoid that desaturates highlights
+    /// gracefully instead of skewing their hue.
+    Agx,
+}
+
+impl ToneMap {
+    /// Maps linear radiance to linear display values in [0, 1].
+    pub fn apply(self, col: vec3::Vec3) -> vec3::Vec3 {
+        let col = match self {
+            ToneMap::Clamp => col,
+            ToneMap::Reinhard => {
+                let l = render::luminance(col);
+                if l <= 0.0 {
+                    col
+                } else {
+                    col * (1.0 / (1.0 + l))
+                }
+            }
+            ToneMap::Aces => {
+                let v = mul(ACES_INPUT, col);
+                let fit = |v: f32| {
+                    (v * (v + 0.024_578_6) - 0.000_090_537)
+                        / (v * (0.983_729 * v + 0.432_951) + 0.238_081)
+                };
+                mul(ACES_OUTPUT, vec3::Vec3::new(fit(v.r()), fit(v.g()), fit(v.b())))
+            }
+            ToneMap::Agx => {
+                let v = mul(AGX_INSET, col);
+                let encode = |x: f32| {
+                    let x = (x.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV) - AGX_MIN_EV)
+                        / (AGX_MAX_EV - AGX_MIN_EV);
+                    let x2 = x * x;
+                    let x4 = x2 * x2;
+                    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+                        + 0.4298 * x2
+                        + 0.1191 * x
+                        - 0.00232
+                };
+                let v = mul(AGX_OUTSET, vec3::Vec3::new(encode(v.r()), encode(v.g()), encode(v.b())));
+                // The curve produces display-encoded values; undo the 2.2 gamma
+                // it was designed for so the sRGB encoding can be applied later.
+                vec3::Vec3::new(
+                    v.r().max(0.0).powf(2.2),
+                    v.g().max(0.0).powf(2.2),
+                    v.b().max(0.0).powf(2.2),
+                )
+            }
+        };
+        vec3::Vec3::new(
+            col.r().clamp(0.0, 1.0),
+            col.g().clamp(0.0, 1.0),
+            col.b().clamp(0.0, 1.0),
+        )
+    }
+}
+
+type Matrix = [[f32; 3]; 3];
+
+const ACES_INPUT: Matrix = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+
+const ACES_OUTPUT: Matrix = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+const AGX_INSET: Matrix = [
+    [0.842_479_06, 0.078_433_6, 0.079_223_745],
+    [0.042_328_242, 0.878_468_6, 0.079_166_13],
+    [0.042_375_655, 0.078_433_6, 0.879_143],
+];
+
+const AGX_OUTSET: Matrix = [
+    [1.196_879, -0.098_020_88, -0.099_029_74],
+    [-0.052_896_85, 1.151_903_1, -0.098_961_18],
+    [-0.052_971_635, -0.098_043_45, 1.151_073_7],
+];
+
+const AGX_MIN_EV: f32 = -12.473_931;
+const AGX_MAX_EV: f32 = 4.026_069;
+
+fn mul(m: Matrix, v: vec3::Vec3) -> vec3::Vec3 {
+    vec3::Vec3::new(
+        m[0][0] * v.r() + m[0][1] * v.g() + m[0][2] * v.b(),
+        m[1][0] * v.r() + m[1][1] * v.g() + m[1][2] * v.b(),
+        m[2][0] * v.r() + m[2][1] * v.g() + m[2][2] * v.b(),
+    )
+}
+
+/// The sRGB opto-electronic transfer function, for linear values in [0, 1].
+pub fn srgb_oetf(x: f32) -> f32 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse of `srgb_oetf`, turning encoded values in [0, 1] back to linear.
+pub fn srgb_eotf(x: f32) -> f32 {
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Turns linear radiance into 8-bit sRGB pixels.
+#[derive(Copy, Clone)]
+pub struct Pipeline {
+    /// Linear scale applied before tone mapping.
+    pub exposure: f32,
+    pub tone_map: ToneMap,
+    /// Adds triangular noise of +-1 code value before quantising, which
+    /// hides banding in smooth gradients such as the sky.
+    pub dither: bool,
+}
+
+impl Pipeline {
+    /// `(x, y)` seeds the dither noise so that output is deterministic.
+    pub fn quantize(&self, col: vec3::Vec3, x: i32, y: i32) -> [u8; 3] {
+        let col = self.tone_map.apply(col * self.exposure);
+        let mut out = [0; 3];
+        for (c, o) in out.iter_mut().enumerate() {
+            let mut v = srgb_oetf(col.e[c]) * 255.0;
+            if self.dither {
+                let h = dither_hash(x as u32, y as u32, c as u32);
+                let a = (h & 0xffff) as f32 / 65536.0;
+                let b = (h >> 16) as f32 / 65536.0;
+                v += a - b;
+            }
+            *o = (v + 0.5).clamp(0.0, 255.0) as u8;
+        }
+        out
+    }
+}
+
+fn dither_hash(x: u32, y: u32, c: u32) -> u32 {
+    let mut h = x.wrapping_mul(0x8da6_b343) ^ y.wrapping_mul(0xd816_3841) ^ c.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
@@ -36,11 +36,11 @@ impl Vec3 {
     }
 
     pub fn length(&self) -> f32 {
-        (&self.e[0] * &self.e[0] + &self.e[1] * &self.e[1] + &self.e[2] * &self.e[2]).sqrt()
+        (self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2]).sqrt()
     }
 
     pub fn squared_length(&self) -> f32 {
-        &self.e[0] * &self.e[0] + &self.e[1] * &self.e[1] + &self.e[2] * &self.e[2]
+        self.e[0] * self.e[0] + self.e[1] * self.e[1] + self.e[2] * self.e[2]
     }
 
     pub fn make_unit_vector(mut self) {
@@ -51,15 +51,15 @@ impl Vec3 {
     }
 
     pub fn dot(&self, v2: Vec3) -> f32 {
-        &self.e[0] * v2.e[0] + &self.e[1] * v2.e[1] + &self.e[2] * v2.e[2]
+        self.e[0] * v2.e[0] + self.e[1] * v2.e[1] + self.e[2] * v2.e[2]
     }
 
     pub fn cross(&self, v2: Vec3) -> Vec3 {
         Vec3 {
             e: [
-                &self.e[1] * v2.e[2] - &self.e[2] * v2.e[1],
-                -(&self.e[0] * v2.e[2] - &self.e[2] * v2.e[0]),
-                &self.e[0] * v2.e[1] - &self.e[1] * v2.e[0],
+                self.e[1] * v2.e[2] - self.e[2] * v2.e[1],
+                -(self.e[0] * v2.e[2] - self.e[2] * v2.e[0]),
+                self.e[0] * v2.e[1] - self.e[1] * v2.e[0],
             ],
         }
     }